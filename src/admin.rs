@@ -0,0 +1,270 @@
+use crate::chat::{
+    ChatServer,
+    admin::{AdminAction, GetUserCount, ListUsers, LookupUser},
+};
+use crate::config::{AdminConfig, WDuration};
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use actix_web::{
+    FromRequest, HttpRequest, HttpResponse,
+    dev::Payload,
+    error::{ErrorBadRequest, ErrorUnauthorized},
+    http::header,
+    web,
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{fs, future};
+use uuid::Uuid;
+
+/// The bearer token required by the admin API.
+pub struct AdminToken(String);
+
+impl AdminToken {
+    pub fn new(cfg: &AdminConfig) -> Result<AdminToken> {
+        let token = fs::read_to_string(&cfg.token_file)?.trim().to_string();
+        if token.is_empty() {
+            return Err(Error::InvalidConfig {
+                message: format!("admin token file {:?} is empty", cfg.token_file),
+            });
+        }
+        Ok(AdminToken(token))
+    }
+
+    /// Compares the token in constant time.
+    fn matches(&self, token: &str) -> bool {
+        let (expected, actual) = (self.0.as_bytes(), token.as_bytes());
+        expected.len() == actual.len()
+            && expected
+                .iter()
+                .zip(actual)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Registers the admin API at `/admin` if a token is configured.
+pub fn configure(cfg: &mut web::ServiceConfig, token: Option<web::Data<AdminToken>>) {
+    let Some(token) = token else {
+        return;
+    };
+    cfg.service(
+        web::scope("/admin")
+            .app_data(token)
+            .route("/users", web::get().to(list_users))
+            .route("/users/{user}", web::get().to(lookup_user))
+            .route("/users/{user}/ban", web::post().to(ban_user))
+            .route("/users/{user}/ban", web::delete().to(unban_user))
+            .route("/users/{user}/mute", web::post().to(mute_user))
+            .route("/users/{user}/mute", web::delete().to(unmute_user))
+            .route("/users/{user}/kick", web::post().to(kick_user))
+            .route("/user-count", web::get().to(user_count))
+            .route("/broadcast", web::post().to(broadcast)),
+    );
+}
+
+/// Extracting this succeeds only if the request carries the admin token.
+struct Authorized;
+
+impl FromRequest for Authorized {
+    type Error = actix_web::Error;
+    type Future = future::Ready<actix_web::Result<Authorized>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let authorized = match (req.app_data::<web::Data<AdminToken>>(), token) {
+            (Some(expected), Some(token)) => expected.matches(token),
+            _ => false,
+        };
+
+        future::ready(if authorized {
+            Ok(Authorized)
+        } else {
+            info!(
+                "Unauthorized admin request to `{}` from {:?}.",
+                req.path(),
+                req.peer_addr()
+            );
+            Err(ErrorUnauthorized("invalid admin token"))
+        })
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct SanctionBody {
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    duration: Option<WDuration>,
+}
+
+#[derive(Deserialize, Default)]
+struct KickBody {
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BroadcastBody {
+    content: String,
+}
+
+async fn list_users(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+) -> actix_web::Result<HttpResponse> {
+    let users = srv.send(ListUsers).await.map_err(internal)?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+async fn lookup_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    query: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    let query = query.into_inner();
+    match srv.send(LookupUser { query }).await.map_err(internal)? {
+        Ok(Some(user)) => Ok(HttpResponse::Ok().json(user)),
+        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Err(err) => Ok(error_response(err)),
+    }
+}
+
+async fn user_count(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+) -> actix_web::Result<HttpResponse> {
+    let count = srv.send(GetUserCount).await.map_err(internal)?;
+    Ok(HttpResponse::Ok().json(count))
+}
+
+async fn ban_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    user: web::Path<Uuid>,
+    body: web::Bytes,
+) -> actix_web::Result<HttpResponse> {
+    let body: SanctionBody = optional_json(&body)?;
+    run(
+        &srv,
+        AdminAction::Ban {
+            user: user.into_inner(),
+            reason: body.reason,
+            duration: body.duration,
+        },
+    )
+    .await
+}
+
+async fn unban_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    user: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    run(
+        &srv,
+        AdminAction::Unban {
+            user: user.into_inner(),
+        },
+    )
+    .await
+}
+
+async fn mute_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    user: web::Path<Uuid>,
+    body: web::Bytes,
+) -> actix_web::Result<HttpResponse> {
+    let body: SanctionBody = optional_json(&body)?;
+    run(
+        &srv,
+        AdminAction::Mute {
+            user: user.into_inner(),
+            reason: body.reason,
+            duration: body.duration,
+        },
+    )
+    .await
+}
+
+async fn unmute_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    user: web::Path<Uuid>,
+) -> actix_web::Result<HttpResponse> {
+    run(
+        &srv,
+        AdminAction::Unmute {
+            user: user.into_inner(),
+        },
+    )
+    .await
+}
+
+async fn kick_user(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    user: web::Path<Uuid>,
+    body: web::Bytes,
+) -> actix_web::Result<HttpResponse> {
+    let body: KickBody = optional_json(&body)?;
+    run(
+        &srv,
+        AdminAction::Kick {
+            user: user.into_inner(),
+            reason: body.reason,
+        },
+    )
+    .await
+}
+
+async fn broadcast(
+    _: Authorized,
+    srv: web::Data<Addr<ChatServer>>,
+    body: web::Json<BroadcastBody>,
+) -> actix_web::Result<HttpResponse> {
+    let content = body.into_inner().content;
+    info!("Admin API has announced `{}`.", content);
+    run(&srv, AdminAction::Broadcast { content }).await
+}
+
+/// Parses a body which may be left out, in which case the defaults are used.
+/// A body which is present has to be valid, so mistakes aren't silently ignored.
+fn optional_json<T: DeserializeOwned + Default>(body: &[u8]) -> actix_web::Result<T> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body).map_err(|err| ErrorBadRequest(format!("invalid body: {}", err)))
+}
+
+/// Runs the action and answers with `204 No Content` if it succeeded.
+async fn run(srv: &Addr<ChatServer>, action: AdminAction) -> actix_web::Result<HttpResponse> {
+    Ok(match srv.send(action).await.map_err(internal)? {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => error_response(err),
+    })
+}
+
+/// Answers with the error in the body, like the `Error` packet.
+fn error_response(err: ClientError) -> HttpResponse {
+    let mut response = match err {
+        ClientError::NotPermitted => HttpResponse::Forbidden(),
+        ClientError::NotBanned | ClientError::NotMuted | ClientError::NotConnected => {
+            HttpResponse::Conflict()
+        }
+        ClientError::Internal => HttpResponse::InternalServerError(),
+        _ => HttpResponse::BadRequest(),
+    };
+    response.json(serde_json::json!({ "message": err }))
+}
+
+fn internal(err: MailboxError) -> actix_web::Error {
+    warn!("Could not reach chat server: {}", err);
+    actix_web::error::ErrorInternalServerError("chat server unavailable")
+}
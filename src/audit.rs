@@ -0,0 +1,137 @@
+use crate::error::*;
+use crate::time::unix_time;
+use log::*;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Ban,
+    Unban,
+    BanIp,
+    UnbanIp,
+    Mute,
+    Unmute,
+    Kick,
+    GrantRole,
+    RevokeRole,
+    Broadcast,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Success,
+    Failure(String),
+}
+
+/// A moderation action which was taken.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// The unix timestamp in seconds.
+    pub timestamp: u64,
+    /// The user who took the action.
+    pub actor: Option<Uuid>,
+    /// The user the action was taken against.
+    pub target: Option<Uuid>,
+    pub action: AuditAction,
+    pub reason: Option<String>,
+    /// Additional information depending on the action, like the duration of a ban.
+    pub details: Option<String>,
+    pub result: AuditResult,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction, target: Option<Uuid>) -> AuditEntry {
+        AuditEntry {
+            timestamp: unix_time(),
+            actor: None,
+            target,
+            action,
+            reason: None,
+            details: None,
+            result: AuditResult::Success,
+        }
+    }
+}
+
+/// An append-only log of moderation actions stored as JSON lines.
+///
+/// The newest `tail_size` entries are kept in memory, so queries don't read the file.
+pub struct AuditLog {
+    file: File,
+    tail: VecDeque<AuditEntry>,
+    tail_size: usize,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, tail_size: usize) -> Result<AuditLog> {
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let mut log = AuditLog {
+            file,
+            tail: VecDeque::new(),
+            tail_size,
+        };
+
+        let reader = BufReader::new(File::open(&path)?);
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(entry) => log.push_tail(entry),
+                Err(err) => warn!("Skipping malformed audit log entry: {}", err),
+            }
+        }
+        Ok(log)
+    }
+
+    pub fn record(&mut self, entry: &AuditEntry) {
+        let res = serde_json::to_string(entry)
+            .map_err(Error::from)
+            .and_then(|line| writeln!(self.file, "{}", line).map_err(Error::from));
+        if let Err(err) = res {
+            error!("Could not write to audit log: {}: {:?}", err, entry);
+        }
+        self.push_tail(entry.clone());
+    }
+
+    fn push_tail(&mut self, entry: AuditEntry) {
+        if self.tail.len() >= self.tail_size {
+            self.tail.pop_front();
+        }
+        if self.tail_size > 0 {
+            self.tail.push_back(entry);
+        }
+    }
+
+    /// Makes sure every recorded entry is on disk.
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Returns at most `limit` of the newest entries kept in memory, oldest first.
+    /// If `target` is set, only entries concerning this user are returned.
+    pub fn query(&self, target: Option<Uuid>, limit: usize) -> Vec<AuditEntry> {
+        let mut entries: Vec<_> = self
+            .tail
+            .iter()
+            .rev()
+            .filter(|entry| target.is_none() || entry.target == target)
+            .take(limit)
+            .cloned()
+            .collect();
+        entries.reverse();
+        entries
+    }
+}
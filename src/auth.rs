@@ -2,48 +2,338 @@ use crate::error::*;
 use awc::{Client, http::StatusCode};
 use log::*;
 
-use serde::{Deserialize, Serialize, de::IgnoredAny};
+use serde::{
+    Deserialize, Serialize,
+    de::{DeserializeOwned, IgnoredAny},
+};
 use url::Url;
 
-use crate::config::AuthConfig;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use crate::config::{AuthConfig, Config, MojangConfig};
+use crate::key::JwtKey;
+use crate::metrics::Metrics;
+use crate::time::unix_time;
+use jsonwebtoken::{Header, Validation};
 use std::{
-    fs,
-    time::{Duration, SystemTime},
+    cell::Cell,
+    collections::HashMap,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use uuid::Uuid;
 
-pub async fn authenticate(username: &str, server_id: &str) -> Result<AuthInfo> {
-    let mut url =
-        Url::parse("https://sessionserver.mojang.com/session/minecraft/hasJoined").unwrap();
-    url.query_pairs_mut()
-        .append_pair("username", username)
-        .append_pair("serverId", server_id);
+/// A client of a Yggdrasil-compatible session server like Mojang's.
+///
+/// Its connections are reused across logins. Requests which fail because the server
+/// can't be reached or answers with a server error are retried. After
+/// `failure_threshold` failed logins in a row, logins are rejected with
+/// `AuthUnavailable` for `unavailable_time` without contacting the server;
+/// afterwards a single login is let through to check whether it is back.
+#[derive(Clone)]
+pub struct SessionServer {
+    client: Client,
+    has_joined: Url,
+    config: MojangConfig,
+    breaker: Rc<Cell<Breaker>>,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// The circuit breakers of the session servers, by URL.
+/// They are kept when the providers are rebuilt on reload,
+/// so a reload doesn't let logins through to a server which is down.
+#[derive(Default)]
+pub struct Breakers(HashMap<String, Rc<Cell<Breaker>>>);
 
-    let client = Client::new();
-    let mut response = client.get(url.as_str()).send().await.map_err(|err| {
-        debug!("Reqwest error: {:?}", err);
-        Error::IO {
-            source: std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+impl SessionServer {
+    fn new(
+        config: &MojangConfig,
+        metrics: Arc<Metrics>,
+        breaker: Rc<Cell<Breaker>>,
+    ) -> SessionServer {
+        SessionServer {
+            client: Client::builder().timeout(*config.timeout).finish(),
+            has_joined: config
+                .has_joined_url()
+                .expect("session server URL was validated"),
+            config: config.clone(),
+            breaker,
+            metrics,
         }
-    })?;
+    }
+
+    /// Checks that `username` has joined the server identified by `server_id`.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        server_id: &str,
+    ) -> std::result::Result<AuthInfo, ClientError> {
+        if !self.allow_request() {
+            debug!(
+                "Session server is unavailable, rejecting login of `{}`.",
+                username
+            );
+            return Err(ClientError::AuthUnavailable);
+        }
+
+        let mut url = self.has_joined.clone();
+        url.query_pairs_mut()
+            .append_pair("username", username)
+            .append_pair("serverId", server_id);
+
+        let start = Instant::now();
+        let res = self.request_with_retries(&url).await;
+        self.metrics.mojang_auth(start.elapsed(), res.is_ok());
+        res
+    }
 
-    if response.status() == StatusCode::OK {
-        response.json::<AuthInfo>().await.map_err(|err| {
+    async fn request_with_retries(&self, url: &Url) -> std::result::Result<AuthInfo, ClientError> {
+        let mut backoff = *self.config.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            match self.request(url).await {
+                Ok(res) => {
+                    self.breaker.set(Breaker::default());
+                    return res;
+                }
+                Err(err) if attempt < self.config.retries => {
+                    debug!("Request to session server failed, retrying: {}", err);
+                    actix_rt::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    warn!(
+                        "Session server {} is unavailable: {}",
+                        self.config.session_server, err
+                    );
+                    self.record_failure();
+                    return Err(ClientError::AuthUnavailable);
+                }
+            }
+        }
+    }
+
+    /// Sends a single request.
+    /// Errors worth retrying are returned in the outer `Result`.
+    async fn request(
+        &self,
+        url: &Url,
+    ) -> std::result::Result<std::result::Result<AuthInfo, ClientError>, String> {
+        let mut response = self
+            .client
+            .get(url.as_str())
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let status = response.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(format!("status-code is {}", status));
+        }
+        if status != StatusCode::OK {
+            debug!("Login status-code is {}", status);
+            return Ok(Err(ClientError::LoginFailed));
+        }
+        Ok(response.json::<AuthInfo>().await.map_err(|err| {
             debug!("JSON deserialization error: {:?}", err);
-            Error::IO {
-                source: std::io::Error::new(std::io::ErrorKind::Other, err),
+            ClientError::LoginFailed
+        }))
+    }
+
+    /// Returns whether the session server may be contacted.
+    fn allow_request(&self) -> bool {
+        let mut breaker = self.breaker.get();
+        match breaker.open_until {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                // Let one login through; the others wait for its result.
+                breaker.open_until = Some(Instant::now() + *self.config.unavailable_time);
+                self.breaker.set(breaker);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn record_failure(&self) {
+        let mut breaker = self.breaker.get();
+        breaker.failures += 1;
+        let threshold = self.config.failure_threshold;
+        if threshold != 0 && breaker.failures >= threshold {
+            if breaker.open_until.is_none() {
+                warn!(
+                    "Session server failed {} times in a row, rejecting logins for {:?}.",
+                    breaker.failures, *self.config.unavailable_time
+                );
             }
+            breaker.open_until = Some(Instant::now() + *self.config.unavailable_time);
+        }
+        self.breaker.set(breaker);
+    }
+}
+
+/// The credential a client logs in with, along with the state of its connection.
+pub struct LoginRequest {
+    /// The credential sent by the client; its format depends on the provider.
+    pub credential: serde_json::Value,
+    /// The hash sent to the client in `MojangInfo`, if it requested one.
+    pub session_hash: Option<String>,
+}
+
+pub type LoginFuture = Pin<Box<dyn Future<Output = std::result::Result<UserInfo, ClientError>>>>;
+
+/// A way for clients to prove who they are.
+pub trait AuthProvider {
+    /// Checks the credential and returns the user it belongs to.
+    fn login(&self, request: LoginRequest) -> LoginFuture;
+}
+
+/// Returns the providers clients can log in with, by name.
+/// Session servers whose URL is in `breakers` keep the state of their circuit breaker;
+/// afterwards it only contains the ones still in use.
+pub fn providers(
+    config: &Config,
+    authenticator: Option<&Rc<Authenticator>>,
+    metrics: &Arc<Metrics>,
+    breakers: &mut Breakers,
+) -> HashMap<String, Rc<dyn AuthProvider>> {
+    let mut previous = std::mem::take(&mut breakers.0);
+    let mut session_server = |config: MojangConfig| {
+        let breaker = breakers
+            .0
+            .entry(config.session_server.clone())
+            .or_insert_with(|| previous.remove(&config.session_server).unwrap_or_default());
+        SessionServer::new(&config, metrics.clone(), breaker.clone())
+    };
+
+    let mut providers: HashMap<String, Rc<dyn AuthProvider>> = HashMap::new();
+    providers.insert(
+        String::from("mojang"),
+        Rc::new(YggdrasilProvider {
+            servers: vec![session_server(config.mojang.clone())],
+        }),
+    );
+    if let Some(authenticator) = authenticator {
+        providers.insert(String::from("jwt"), authenticator.clone());
+    }
+    if config.providers.offline {
+        warn!("Offline logins are enabled, anyone can log in as any user.");
+        providers.insert(String::from("offline"), Rc::new(OfflineProvider));
+    }
+    for (name, urls) in &config.providers.yggdrasil {
+        let servers = urls
+            .iter()
+            .map(|url| session_server(config.mojang.with_session_server(url)))
+            .collect();
+        providers.insert(name.clone(), Rc::new(YggdrasilProvider { servers }));
+    }
+    providers
+}
+
+fn parse_credential<T: DeserializeOwned>(
+    credential: serde_json::Value,
+) -> std::result::Result<T, ClientError> {
+    serde_json::from_value(credential).map_err(|err| {
+        debug!("Malformed credential: {}", err);
+        ClientError::LoginFailed
+    })
+}
+
+#[derive(Deserialize)]
+struct SessionCredential {
+    name: String,
+    /// The uuid the client claims to have; it is taken from the session server otherwise.
+    #[serde(default)]
+    uuid: Option<Uuid>,
+}
+
+/// Verifies logins with the Yggdrasil-compatible session servers in order,
+/// until one of them knows the user.
+/// Clients have to join the server with the hash from `MojangInfo` beforehand.
+struct YggdrasilProvider {
+    servers: Vec<SessionServer>,
+}
+
+impl AuthProvider for YggdrasilProvider {
+    fn login(&self, request: LoginRequest) -> LoginFuture {
+        let servers = self.servers.clone();
+        Box::pin(async move {
+            let session_hash = request
+                .session_hash
+                .ok_or(ClientError::MojangRequestMissing)?;
+            let credential: SessionCredential = parse_credential(request.credential)?;
+
+            let mut last_err = ClientError::LoginFailed;
+            for server in &servers {
+                match server.authenticate(&credential.name, &session_hash).await {
+                    Ok(info) if credential.uuid.is_some_and(|uuid| uuid != info.id) => {
+                        return Err(ClientError::InvalidId);
+                    }
+                    Ok(info) => {
+                        return Ok(UserInfo {
+                            name: info.name,
+                            uuid: info.id,
+                        });
+                    }
+                    // The user may be known to one of the other servers.
+                    Err(ClientError::LoginFailed) => {}
+                    Err(err) => last_err = err,
+                }
+            }
+            Err(last_err)
         })
-    } else {
-        debug!("Login status-code is {}", response.status());
-        Err(ClientError::LoginFailed.into())
+    }
+}
+
+impl AuthProvider for Authenticator {
+    fn login(&self, request: LoginRequest) -> LoginFuture {
+        #[derive(Deserialize)]
+        struct JwtCredential {
+            token: String,
+        }
+
+        let res = parse_credential::<JwtCredential>(request.credential).and_then(|credential| {
+            self.auth(&credential.token).map_err(|err| {
+                debug!("Invalid JWT: {}", err);
+                ClientError::LoginFailed
+            })
+        });
+        Box::pin(std::future::ready(res))
+    }
+}
+
+/// Accepts any valid Minecraft name and uuid without proof.
+/// It is only meant for development and tests.
+struct OfflineProvider;
+
+impl AuthProvider for OfflineProvider {
+    fn login(&self, request: LoginRequest) -> LoginFuture {
+        let res = parse_credential::<UserInfo>(request.credential).and_then(|info| {
+            let valid = (1..=16).contains(&info.name.len())
+                && info
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if valid {
+                Ok(info)
+            } else {
+                Err(ClientError::LoginFailed)
+            }
+        });
+        Box::pin(std::future::ready(res))
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AuthInfo {
-    pub id: String,
+    pub id: Uuid,
     pub name: String,
     #[serde(rename = "properties")]
     _properties: IgnoredAny,
@@ -81,43 +371,149 @@ pub fn encode_sha1_bytes(bytes: &[u8; 20]) -> String {
     buf
 }
 
+/// Issues and verifies the tokens clients log in with.
+///
+/// Tokens are signed with the active key and carry its id in the `kid` header.
+/// Every configured key is accepted, so keys can be rotated by adding a new active key
+/// and removing the old one once the tokens signed with it have expired.
 pub struct Authenticator {
-    validation: Validation,
-    header: Header,
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    keys: Vec<KeyEntry>,
+    /// The index of the key new tokens are signed with.
+    active: Option<usize>,
     valid_time: Duration,
 }
 
+struct KeyEntry {
+    /// Missing for the single key configured without `auth.keys`.
+    kid: Option<String>,
+    key: JwtKey,
+    validation: Validation,
+}
+
+impl KeyEntry {
+    fn new(kid: Option<String>, key: JwtKey) -> KeyEntry {
+        KeyEntry {
+            kid,
+            validation: Validation::new(key.algorithm),
+            key,
+        }
+    }
+
+    /// Returns whether a token with the `kid` header might be signed with this key.
+    fn matches(&self, kid: Option<&str>) -> bool {
+        match (self.kid.as_deref(), kid) {
+            (Some(expected), Some(kid)) => expected == kid,
+            _ => true,
+        }
+    }
+}
+
 impl Authenticator {
     pub fn new(cfg: &AuthConfig) -> Result<Authenticator> {
-        let key_data = fs::read(&cfg.key_file)?;
+        if cfg.keys.is_empty() {
+            let key = JwtKey::load(
+                cfg.algorithm,
+                cfg.key_file.as_deref(),
+                cfg.public_key_file.as_deref(),
+            )?;
+            let active = key.encoding.as_ref().map(|_| 0);
+            return Ok(Authenticator {
+                keys: vec![KeyEntry::new(None, key)],
+                active,
+                valid_time: *cfg.valid_time,
+            });
+        }
+
+        let mut keys = Vec::with_capacity(cfg.keys.len());
+        for key_cfg in &cfg.keys {
+            let key = JwtKey::load(
+                key_cfg.algorithm.unwrap_or(cfg.algorithm),
+                key_cfg.key_file.as_deref(),
+                key_cfg.public_key_file.as_deref(),
+            )?;
+            keys.push(KeyEntry::new(Some(key_cfg.kid.clone()), key));
+        }
+
+        let active = match &cfg.active_key {
+            Some(kid) => {
+                let index = keys
+                    .iter()
+                    .position(|entry| entry.kid.as_ref() == Some(kid))
+                    .expect("active key is validated");
+                if keys[index].key.encoding.is_none() {
+                    return Err(Error::InvalidConfig {
+                        message: format!("the active key `{}` needs a `key_file`", kid),
+                    });
+                }
+                Some(index)
+            }
+            None => {
+                if keys.iter().any(|entry| entry.key.encoding.is_some()) {
+                    warn!("No `auth.active_key` is set, so no tokens can be issued.");
+                }
+                None
+            }
+        };
 
         Ok(Authenticator {
-            validation: Validation::new(cfg.algorithm),
-            header: Header::new(cfg.algorithm),
-            encoding_key: EncodingKey::from_secret(&key_data),
-            decoding_key: DecodingKey::from_secret(&key_data),
+            keys,
+            active,
             valid_time: *cfg.valid_time,
         })
     }
 
+    /// Returns whether new tokens can be issued,
+    /// which requires the secret or private key.
+    pub fn can_sign(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Verifies the token with the key named by its `kid` header.
+    /// Tokens without one are checked against every key of their algorithm.
     pub fn auth(&self, token: &str) -> Result<UserInfo> {
-        match jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &self.validation) {
+        let header = jsonwebtoken::decode_header(token)?;
+        let mut result = Err(jsonwebtoken::errors::ErrorKind::InvalidSignature.into());
+        for entry in &self.keys {
+            if !entry.matches(header.kid.as_deref()) || entry.key.algorithm != header.alg {
+                continue;
+            }
+            result = jsonwebtoken::decode::<Claims>(token, &entry.key.decoding, &entry.validation);
+            if result.is_ok() {
+                break;
+            }
+        }
+        match result {
             Ok(data) => Ok(data.claims.user),
             Err(err) => Err(err.into()),
         }
     }
 
     pub fn new_token(&self, info: UserInfo) -> Result<String> {
-        let unix_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("system time is somehow before the unix epoch");
         let claims = Claims {
-            exp: (unix_time + self.valid_time).as_secs() as usize,
+            exp: (unix_time() + self.valid_time.as_secs()) as usize,
             user: info,
         };
-        jsonwebtoken::encode(&self.header, &claims, &self.encoding_key).map_err(|err| err.into())
+        let entry = &self.keys[self.active.ok_or(ClientError::NotSupported)?];
+        let header = Header {
+            kid: entry.kid.clone(),
+            ..Header::new(entry.key.algorithm)
+        };
+        let key = entry
+            .key
+            .encoding
+            .as_ref()
+            .expect("the active key can sign");
+        jsonwebtoken::encode(&header, &claims, key).map_err(|err| err.into())
+    }
+
+    /// Returns the public keys as a JSON Web Key Set.
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<_> = self
+            .keys
+            .iter()
+            .filter_map(|entry| entry.key.jwk(entry.kid.as_deref()))
+            .collect();
+        serde_json::json!({ "keys": keys })
     }
 }
 
@@ -0,0 +1,88 @@
+use crate::config::WDuration;
+use crate::error::ClientError;
+use crate::moderation::Sanction;
+
+use actix::*;
+use serde::Serialize;
+use std::{collections::BTreeSet, net::IpAddr};
+use uuid::Uuid;
+
+/// Returns every user who is currently logged in.
+#[derive(Message)]
+#[rtype(result = "Vec<OnlineUser>")]
+pub struct ListUsers;
+
+#[derive(Serialize)]
+pub struct OnlineUser {
+    pub name: String,
+    pub uuid: Uuid,
+    pub anonymous: bool,
+    /// The amount of connections the user is logged in on.
+    pub connections: usize,
+}
+
+/// Looks up a user by uuid or name, whether they are online or not.
+#[derive(Message)]
+#[rtype(result = "Result<Option<UserDetails>, ClientError>")]
+pub struct LookupUser {
+    /// A uuid or a name; names are compared ignoring case.
+    pub query: String,
+}
+
+#[derive(Serialize)]
+pub struct UserDetails {
+    pub uuid: Uuid,
+    pub name: String,
+    /// The amount of connections the user is logged in on.
+    pub connections: usize,
+    /// The IP addresses of these connections.
+    pub addresses: BTreeSet<IpAddr>,
+    /// The unix timestamp in seconds of the first login.
+    pub first_seen: Option<u64>,
+    /// The unix timestamp in seconds of the last login.
+    pub last_seen: Option<u64>,
+    pub roles: Vec<String>,
+    pub ban: Option<Sanction>,
+    pub mute: Option<Sanction>,
+}
+
+/// Returns the amount of connections and logged in users.
+#[derive(Message)]
+#[rtype(result = "UserCount")]
+pub struct GetUserCount;
+
+#[derive(Serialize, MessageResponse)]
+pub struct UserCount {
+    pub connections: u32,
+    pub logged_in: u32,
+}
+
+/// A moderation action taken through the admin API.
+/// It is handled like the packet of the same name, without an acting user.
+#[derive(Message)]
+#[rtype(result = "Result<(), ClientError>")]
+pub enum AdminAction {
+    Ban {
+        user: Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    },
+    Unban {
+        user: Uuid,
+    },
+    Mute {
+        user: Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    },
+    Unmute {
+        user: Uuid,
+    },
+    Kick {
+        user: Uuid,
+        reason: Option<String>,
+    },
+    Broadcast {
+        content: String,
+    },
+}
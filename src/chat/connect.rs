@@ -1,17 +1,19 @@
 use log::*;
 
-use super::{ChatServer, ClientPacket, InternalId, SessionState};
+use super::{ChatServer, InternalId, SessionState, session::Session};
 use actix::*;
+use std::net::IpAddr;
 
 #[derive(Message)]
 #[rtype(InternalId)]
 pub(super) struct Connect {
-    addr: Recipient<ClientPacket>,
+    addr: Addr<Session>,
+    ip: Option<IpAddr>,
 }
 
 impl Connect {
-    pub fn new(addr: Recipient<ClientPacket>) -> Connect {
-        Connect { addr }
+    pub fn new(addr: Addr<Session>, ip: Option<IpAddr>) -> Connect {
+        Connect { addr, ip }
     }
 }
 
@@ -25,11 +27,17 @@ impl Handler<Connect> for ChatServer {
             id,
             SessionState {
                 addr: msg.addr.clone(),
+                ip: msg.ip,
                 session_hash: None,
                 user: None,
             },
         );
-        debug!("User `{}` joined the chat.", id);
+        for channel in self.channels.values_mut() {
+            if channel.default_joined {
+                channel.members.insert(id);
+            }
+        }
+        debug!("User `{}` joined the chat from {:?}.", id, msg.ip);
         id
     }
 }
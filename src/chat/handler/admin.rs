@@ -0,0 +1,120 @@
+use crate::chat::{
+    ChatServer, User,
+    admin::{AdminAction, GetUserCount, ListUsers, LookupUser, OnlineUser, UserCount, UserDetails},
+};
+use crate::error::*;
+use log::*;
+
+use actix::*;
+use uuid::Uuid;
+
+impl Handler<ListUsers> for ChatServer {
+    type Result = Vec<OnlineUser>;
+
+    fn handle(&mut self, _msg: ListUsers, _ctx: &mut Context<Self>) -> Vec<OnlineUser> {
+        self.users
+            .iter()
+            .map(|(uuid, session)| OnlineUser {
+                name: session.name.clone(),
+                uuid: *uuid,
+                anonymous: session.anonymous,
+                connections: session.connections.len(),
+            })
+            .collect()
+    }
+}
+
+impl Handler<LookupUser> for ChatServer {
+    type Result = std::result::Result<Option<UserDetails>, ClientError>;
+
+    fn handle(
+        &mut self,
+        LookupUser { query }: LookupUser,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        self.lookup_user(&query).map_err(into_client_error)
+    }
+}
+
+impl ChatServer {
+    fn lookup_user(&self, query: &str) -> Result<Option<UserDetails>> {
+        let uuid = query.parse::<Uuid>().ok();
+        let matches = |user: &User| match uuid {
+            Some(uuid) => user.uuid == uuid,
+            None => user.name.eq_ignore_ascii_case(query),
+        };
+        let online = self
+            .connections
+            .values()
+            .filter_map(|session| session.user.as_ref())
+            .find(|user| matches(user));
+        let record = match (online, uuid) {
+            (Some(user), _) => self.moderation.user(&user.uuid)?,
+            (None, Some(uuid)) => self.moderation.user(&uuid)?,
+            (None, None) => self.moderation.user_by_name(query)?,
+        };
+
+        let (uuid, name) = match (online, &record) {
+            (Some(user), _) => (user.uuid, user.name.clone()),
+            (None, Some(record)) => (record.uuid, record.name.clone()),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(UserDetails {
+            uuid,
+            name,
+            connections: self.connections_of(&uuid).count(),
+            addresses: self
+                .connections_of(&uuid)
+                .filter_map(|(_, session)| session.ip)
+                .collect(),
+            first_seen: record.as_ref().map(|record| record.first_seen),
+            last_seen: record.as_ref().map(|record| record.last_seen),
+            roles: self.moderation.roles_of(&uuid).map(String::from).collect(),
+            ban: self.moderation.get_ban(&uuid).cloned(),
+            mute: self.moderation.get_mute(&uuid).cloned(),
+        }))
+    }
+}
+
+impl Handler<GetUserCount> for ChatServer {
+    type Result = UserCount;
+
+    fn handle(&mut self, _msg: GetUserCount, _ctx: &mut Context<Self>) -> UserCount {
+        self.user_count()
+    }
+}
+
+impl Handler<AdminAction> for ChatServer {
+    type Result = std::result::Result<(), ClientError>;
+
+    fn handle(&mut self, action: AdminAction, _ctx: &mut Context<Self>) -> Self::Result {
+        let res = match action {
+            AdminAction::Ban {
+                user,
+                reason,
+                duration,
+            } => self.ban(None, &user, reason, duration),
+            AdminAction::Unban { user } => self.unban(None, &user),
+            AdminAction::Mute {
+                user,
+                reason,
+                duration,
+            } => self.mute(None, &user, reason, duration),
+            AdminAction::Unmute { user } => self.unmute(None, &user),
+            AdminAction::Kick { user, reason } => self.kick(None, &user, reason),
+            AdminAction::Broadcast { content } => self.broadcast(None, content),
+        };
+        res.map_err(into_client_error)
+    }
+}
+
+/// Internal errors are logged and hidden from the admin API.
+fn into_client_error(err: Error) -> ClientError {
+    match err {
+        Error::AxoChat { source } => source,
+        err => {
+            warn!("Admin request failed: {}", err);
+            ClientError::Internal
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::chat::{ChatServer, ClientPacket, InternalId};
+use crate::error::*;
+use crate::moderation::Permission;
+use log::*;
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn handle_request_alts(&mut self, user_id: InternalId, user: &Uuid) {
+        if self
+            .check_permission(user_id, Permission::ViewAlts)
+            .is_none()
+        {
+            return;
+        }
+
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+        match self.moderation.alts(user) {
+            Ok(alts) => session
+                .addr
+                .do_send(ClientPacket::Alts { user: *user, alts }),
+            Err(err) => {
+                warn!("Could not look up alts of `{}`: {}", user, err);
+                session.addr.do_send(ClientPacket::Error {
+                    message: ClientError::Internal,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::*;
+use log::*;
+
+use crate::chat::{ChatServer, ClientPacket, InternalId, User};
+use rand::Rng;
+use uuid::Builder;
+
+impl ChatServer {
+    pub(super) fn handle_login_anonymous(&mut self, user_id: InternalId) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        if session.is_logged_in() {
+            info!("User `{}` tried to log in multiple times.", user_id);
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::AlreadyLoggedIn,
+            });
+            return;
+        }
+
+        if !self
+            .config
+            .auth
+            .as_ref()
+            .is_some_and(|auth| auth.allow_anonymous)
+        {
+            info!("User `{}` tried to log in anonymously.", user_id);
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::NotSupported,
+            });
+            return;
+        }
+
+        // Guests get a random name; if it is taken, the following ones are tried.
+        let start = self.rng.random::<u16>();
+        let name = (0..=u16::MAX)
+            .map(|offset| format!("Guest-{:04x}", start.wrapping_add(offset)))
+            .find(|name| self.user_by_name(name).is_none());
+        let Some(name) = name else {
+            warn!(
+                "User `{}` could not log in, all guest names are taken.",
+                user_id
+            );
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::LoginFailed,
+            });
+            return;
+        };
+        let uuid = Builder::from_random_bytes(self.rng.random()).into_uuid();
+
+        info!(
+            "User `{}` logged in anonymously as `{}` with uuid `{}`",
+            user_id, name, uuid
+        );
+        self.login_user(
+            user_id,
+            User {
+                name,
+                uuid,
+                allow_messages: false,
+                anonymous: true,
+            },
+        );
+    }
+}
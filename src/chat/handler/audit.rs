@@ -0,0 +1,27 @@
+use crate::chat::{ChatServer, ClientPacket, InternalId};
+use crate::moderation::Permission;
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn handle_request_audit_log(
+        &mut self,
+        user_id: InternalId,
+        target: Option<Uuid>,
+        limit: usize,
+    ) {
+        if self
+            .check_permission(user_id, Permission::ViewAuditLog)
+            .is_none()
+        {
+            return;
+        }
+
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+        let limit = limit.min(self.config.moderation.audit_log_tail);
+        let entries = self.audit_log.query(target, limit);
+        session.addr.do_send(ClientPacket::AuditLog { entries });
+    }
+}
@@ -1,67 +1,82 @@
 use super::{ChatServer, ClientPacket};
-use crate::chat::{InternalId, SuccessReason};
-
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{CloseSession, InternalId, SuccessReason};
+use crate::config::WDuration;
 use crate::error::*;
+use crate::moderation::Permission;
+
 use log::*;
 use uuid::Uuid;
 
 impl ChatServer {
-    pub(super) fn ban_user(&mut self, user_id: InternalId, to_ban: &Uuid) {
-        self.handle_user(user_id, to_ban, true);
+    pub(super) fn ban_user(
+        &mut self,
+        user_id: InternalId,
+        to_ban: &Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    ) {
+        self.moderate_user(
+            user_id,
+            Permission::Ban,
+            SuccessReason::Ban,
+            |server, issuer| server.ban(Some(issuer), to_ban, reason, duration),
+        );
     }
 
-    pub(super) fn unban_user(&mut self, user_id: InternalId, to_unban: &Uuid) {
-        self.handle_user(user_id, to_unban, false);
+    /// Bans the user on behalf of `issuer` and informs the banned user.
+    pub(super) fn ban(
+        &mut self,
+        issuer: Option<Uuid>,
+        to_ban: &Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::Ban, Some(*to_ban));
+        entry.reason = reason.clone();
+        entry.details = duration.map(|duration| humantime::format_duration(*duration).to_string());
+
+        self.moderate(issuer, entry, |moderation| {
+            moderation.ban(to_ban, issuer, reason, duration.map(|d| *d))
+        })?;
+        self.notify_banned(to_ban);
+        Ok(())
     }
 
-    fn handle_user(&mut self, user_id: InternalId, receiver: &Uuid, ban: bool) {
-        let session = self
-            .connections
-            .get(&user_id)
-            .expect("could not find connection");
-        if let Some(info) = &session.user {
-            if !self.moderation.is_moderator(&info.uuid) {
-                info!("`{}` tried to (un-)ban user without permission", user_id);
-                session.addr.do_send(ClientPacket::Error {
-                    message: ClientError::NotPermitted,
-                });
-                return;
-            }
+    /// Informs every connection of a newly banned user about the ban
+    /// and closes them if configured.
+    pub(super) fn notify_banned(&self, user: &Uuid) {
+        let Some(ban) = self.moderation.get_ban(user) else {
+            return;
+        };
+        let close_code = self.config.moderation.ban_close_code;
 
-            let res = if ban {
-                self.moderation.ban(receiver)
-            } else {
-                self.moderation.unban(receiver)
-            };
-            match res {
-                Ok(()) => {
-                    let reason = if ban {
-                        info!("User `{}` banned.", receiver);
-                        SuccessReason::Ban
-                    } else {
-                        info!("User `{}` unbanned.", receiver);
-                        SuccessReason::Unban
-                    };
-                    session.addr.do_send(ClientPacket::Success { reason });
-                }
-                Err(Error::AxoChat { source }) => {
-                    info!("Could not (un-)ban user `{}`: {}", receiver, source);
-                    session
-                        .addr
-                        .do_send(ClientPacket::Error { message: source });
-                }
-                Err(err) => {
-                    info!("Could not (un-)ban user `{}`: {}", receiver, err);
-                    session.addr.do_send(ClientPacket::Error {
-                        message: ClientError::Internal,
-                    });
-                }
-            }
-        } else {
-            info!("`{}` is not logged in.", user_id);
-            session.addr.do_send(ClientPacket::Error {
-                message: ClientError::NotLoggedIn,
+        for (id, session) in self.connections_of(user) {
+            session.addr.do_send(ClientPacket::Banned {
+                reason: ban.reason.clone(),
+                until: ban.until,
             });
+            if let Some(code) = close_code {
+                info!("Disconnecting `{}` of banned user `{}`.", id, user);
+                session.addr.do_send(CloseSession {
+                    code,
+                    reason: ban.reason.clone(),
+                });
+            }
         }
     }
+
+    pub(super) fn unban_user(&mut self, user_id: InternalId, to_unban: &Uuid) {
+        self.moderate_user(
+            user_id,
+            Permission::Ban,
+            SuccessReason::Unban,
+            |server, issuer| server.unban(Some(issuer), to_unban),
+        );
+    }
+
+    pub(super) fn unban(&mut self, issuer: Option<Uuid>, to_unban: &Uuid) -> Result<()> {
+        let entry = AuditEntry::new(AuditAction::Unban, Some(*to_unban));
+        self.moderate(issuer, entry, |moderation| moderation.unban(to_unban))
+    }
 }
@@ -0,0 +1,35 @@
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{ChatServer, ClientPacket, InternalId, SuccessReason};
+use crate::error::*;
+use crate::moderation::Permission;
+use log::*;
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn handle_broadcast(&mut self, user_id: InternalId, content: String) {
+        self.moderate_user(
+            user_id,
+            Permission::Broadcast,
+            SuccessReason::Broadcast,
+            |server, actor| {
+                info!("User `{}` has announced `{}`.", user_id, content);
+                server.broadcast(Some(actor), content)
+            },
+        );
+    }
+
+    /// Sends an announcement to every connection on behalf of `actor`.
+    /// Announcements are subject to the same limits as chat messages.
+    pub(super) fn broadcast(&mut self, actor: Option<Uuid>, content: String) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::Broadcast, None);
+        entry.details = Some(content.clone());
+        let validation = self.validator.validate(&content);
+        self.moderate(actor, entry, |_| validation)?;
+
+        let client_packet = ClientPacket::Announcement { content };
+        for session in self.connections.values() {
+            session.addr.do_send(client_packet.clone());
+        }
+        Ok(())
+    }
+}
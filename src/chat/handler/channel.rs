@@ -0,0 +1,93 @@
+use crate::chat::{ChannelInfo, ChatServer, ClientPacket, InternalId, SuccessReason};
+use crate::error::*;
+use log::*;
+
+impl ChatServer {
+    pub(super) fn handle_join_channel(&mut self, user_id: InternalId, name: &str) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        if let Some(channel) = self.channels.get_mut(name) {
+            channel.members.insert(user_id);
+            debug!("User `{}` joined channel `{}`.", user_id, name);
+            session.addr.do_send(ClientPacket::Success {
+                reason: SuccessReason::JoinChannel,
+            });
+        } else {
+            info!(
+                "User `{}` tried to join unknown channel `{}`.",
+                user_id, name
+            );
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::UnknownChannel,
+            });
+        }
+    }
+
+    pub(super) fn handle_leave_channel(&mut self, user_id: InternalId, name: &str) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        let error = match self.channels.get_mut(name) {
+            Some(channel) if channel.members.contains(&user_id) => {
+                channel.members.remove(&user_id);
+                debug!("User `{}` left channel `{}`.", user_id, name);
+                session.addr.do_send(ClientPacket::Success {
+                    reason: SuccessReason::LeaveChannel,
+                });
+                return;
+            }
+            Some(_) => ClientError::NotInChannel,
+            None => ClientError::UnknownChannel,
+        };
+        info!(
+            "User `{}` could not leave channel `{}`: {}",
+            user_id, name, error
+        );
+        session.addr.do_send(ClientPacket::Error { message: error });
+    }
+
+    pub(super) fn handle_list_channels(&mut self, user_id: InternalId) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        let mut channels: Vec<ChannelInfo> = self
+            .channels
+            .iter()
+            .map(|(name, channel)| ChannelInfo {
+                name: name.clone(),
+                description: channel.description.clone(),
+                joined: channel.members.contains(&user_id),
+            })
+            .collect();
+        channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        session.addr.do_send(ClientPacket::Channels { channels });
+    }
+
+    /// Returns if the connection is a member of `channel`.
+    pub(super) fn check_channel(&self, user_id: InternalId, channel: &str) -> bool {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        let error = match self.channels.get(channel) {
+            Some(channel) if channel.members.contains(&user_id) => return true,
+            Some(_) => ClientError::NotInChannel,
+            None => ClientError::UnknownChannel,
+        };
+        info!(
+            "User `{}` tried to access channel `{}`: {}",
+            user_id, channel, error
+        );
+        session.addr.do_send(ClientPacket::Error { message: error });
+        false
+    }
+}
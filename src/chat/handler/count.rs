@@ -1,35 +1,33 @@
-use crate::chat::{ChatServer, ClientPacket, InternalId};
-use crate::error::*;
-use log::*;
+use crate::chat::{ChatServer, ClientPacket, InternalId, admin::UserCount};
+use crate::moderation::Permission;
 
 impl ChatServer {
     pub(super) fn send_user_count(&mut self, user_id: InternalId) {
-        let session = self
-            .connections
-            .get(&user_id)
-            .expect("could not find connection");
-
-        if let Some(info) = &session.user {
-            if !self.moderation.is_moderator(&info.uuid) {
-                info!(
-                    "`{}` tried to get the user count without permission",
-                    user_id
-                );
-                session.addr.do_send(ClientPacket::Error {
-                    message: ClientError::NotPermitted,
-                });
-                return;
-            }
+        if self
+            .check_permission(user_id, Permission::ViewUserCount)
+            .is_none()
+        {
+            return;
+        }
 
-            session.addr.do_send(ClientPacket::UserCount {
-                connections: self.connections.len() as u32,
-                logged_in: self.users.len() as u32,
-            });
-        } else {
-            info!("`{}` is not logged in.", user_id);
-            session.addr.do_send(ClientPacket::Error {
-                message: ClientError::NotLoggedIn,
+        let UserCount {
+            connections,
+            logged_in,
+        } = self.user_count();
+        self.connections
+            .get(&user_id)
+            .expect("could not find connection")
+            .addr
+            .do_send(ClientPacket::UserCount {
+                connections,
+                logged_in,
             });
+    }
+
+    pub(super) fn user_count(&self) -> UserCount {
+        UserCount {
+            connections: self.connections.len() as u32,
+            logged_in: self.users.len() as u32,
         }
     }
 }
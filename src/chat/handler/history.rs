@@ -0,0 +1,44 @@
+use crate::chat::{ChatServer, ClientPacket, InternalId};
+
+impl ChatServer {
+    pub(super) fn handle_request_history(
+        &mut self,
+        user_id: InternalId,
+        channel: String,
+        before: Option<u64>,
+        limit: usize,
+    ) {
+        if !self.check_channel(user_id, &channel) {
+            return;
+        }
+
+        let messages = self.history.query(&channel, before, limit);
+        self.connections
+            .get(&user_id)
+            .expect("could not find connection")
+            .addr
+            .do_send(ClientPacket::History { channel, messages });
+    }
+
+    /// Sends the newest messages of every joined channel as configured in
+    /// `history_on_login`.
+    pub(super) fn send_login_history(&self, user_id: InternalId) {
+        let limit = self.config.message.history_on_login;
+        if limit == 0 {
+            return;
+        }
+
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+        for (name, channel) in &self.channels {
+            if channel.members.contains(&user_id) {
+                session.addr.do_send(ClientPacket::History {
+                    channel: name.clone(),
+                    messages: self.history.query(name, None, limit),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::{ChatServer, ClientPacket};
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{CheckAddress, CloseSession, InternalId, SuccessReason};
+use crate::config::WDuration;
+use crate::error::*;
+use crate::ip::IpRange;
+use crate::moderation::Permission;
+
+use actix::*;
+use log::*;
+use uuid::Uuid;
+
+impl Handler<CheckAddress> for ChatServer {
+    type Result = Option<ClientPacket>;
+
+    fn handle(
+        &mut self,
+        CheckAddress { ip }: CheckAddress,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        self.moderation
+            .get_ip_ban(ip)
+            .map(|ban| ClientPacket::Banned {
+                reason: ban.reason.clone(),
+                until: ban.until,
+            })
+    }
+}
+
+impl ChatServer {
+    pub(super) fn ban_ip(
+        &mut self,
+        user_id: InternalId,
+        range: IpRange,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+        force: bool,
+    ) {
+        self.moderate_user(
+            user_id,
+            Permission::Ban,
+            SuccessReason::BanIp,
+            |server, issuer| {
+                if range.is_broad() && !force {
+                    info!("`{}` tried to ban the broad range `{}`.", user_id, range);
+                    return Err(ClientError::NotPermitted.into());
+                }
+                if server.covers_staff(range) {
+                    info!("`{}` tried to ban staff in `{}`.", user_id, range);
+                    return Err(ClientError::NotPermitted.into());
+                }
+                server.ban_range(Some(issuer), range, reason, duration)
+            },
+        );
+    }
+
+    /// Returns whether the range contains the address of a connected user who may ban,
+    /// like the issuer of the ban.
+    fn covers_staff(&self, range: IpRange) -> bool {
+        self.connections.values().any(|session| {
+            session.ip.is_some_and(|ip| range.contains(ip))
+                && session
+                    .user
+                    .as_ref()
+                    .is_some_and(|user| self.moderation.has_permission(&user.uuid, Permission::Ban))
+        })
+    }
+
+    /// Bans the range on behalf of `issuer` and informs the connections from it.
+    pub(super) fn ban_range(
+        &mut self,
+        issuer: Option<Uuid>,
+        range: IpRange,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::BanIp, None);
+        entry.reason = reason.clone();
+        entry.details = Some(match duration {
+            Some(duration) => format!("{} for {}", range, humantime::format_duration(*duration)),
+            None => range.to_string(),
+        });
+
+        self.moderate(issuer, entry, |moderation| {
+            moderation.ban_ip(range, issuer, reason, duration.map(|d| *d))
+        })?;
+        self.notify_ip_banned(range);
+        Ok(())
+    }
+
+    /// Informs every connection from the range about the ban and closes them if configured.
+    fn notify_ip_banned(&self, range: IpRange) {
+        let close_code = self.config.moderation.ban_close_code;
+        for (id, session) in &self.connections {
+            let Some(ban) = session
+                .ip
+                .filter(|ip| range.contains(*ip))
+                .and_then(|ip| self.moderation.get_ip_ban(ip))
+            else {
+                continue;
+            };
+            session.addr.do_send(ClientPacket::Banned {
+                reason: ban.reason.clone(),
+                until: ban.until,
+            });
+            if let Some(code) = close_code {
+                info!("Disconnecting `{}` from banned range `{}`.", id, range);
+                session.addr.do_send(CloseSession {
+                    code,
+                    reason: ban.reason.clone(),
+                });
+            }
+        }
+    }
+
+    pub(super) fn unban_ip(&mut self, user_id: InternalId, range: IpRange) {
+        self.moderate_user(
+            user_id,
+            Permission::Ban,
+            SuccessReason::UnbanIp,
+            |server, issuer| server.unban_range(Some(issuer), range),
+        );
+    }
+
+    pub(super) fn unban_range(&mut self, issuer: Option<Uuid>, range: IpRange) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::UnbanIp, None);
+        entry.details = Some(range.to_string());
+        self.moderate(issuer, entry, |moderation| moderation.unban_ip(range))
+    }
+}
@@ -3,9 +3,8 @@ use log::*;
 
 use super::{ChatServer, ClientPacket};
 use crate::auth::UserInfo;
-use crate::chat::{InternalId, SuccessReason, User, UserSession};
-use crate::message::RateLimiter;
-use std::collections::HashSet;
+use crate::chat::InternalId;
+use actix::*;
 
 impl ChatServer {
     pub(super) fn handle_request_jwt(&mut self, user_id: InternalId) {
@@ -13,8 +12,16 @@ impl ChatServer {
             .connections
             .get(&user_id)
             .expect("could not find connection");
-        if let Some(auth) = &self.authenticator {
+        if let Some(auth) = self.authenticator.as_ref().filter(|auth| auth.can_sign()) {
             if let Some(user) = &session.user {
+                if user.anonymous {
+                    info!("Anonymous user `{}` tried to get JWT.", user_id);
+                    session.addr.do_send(ClientPacket::Error {
+                        message: ClientError::NotPermitted,
+                    });
+                    return;
+                }
+
                 let token = match auth.new_token(UserInfo {
                     name: user.name.clone(),
                     uuid: user.uuid,
@@ -47,47 +54,17 @@ impl ChatServer {
     pub(super) fn handle_login_jwt(
         &mut self,
         user_id: InternalId,
-        jwt: &str,
+        token: String,
         allow_messages: bool,
+        ctx: &mut Context<Self>,
     ) {
-        let session = self
-            .connections
-            .get_mut(&user_id)
-            .expect("could not find connection");
-        if let Some(auth) = &self.authenticator {
-            match auth.auth(jwt) {
-                Ok(info) => {
-                    self.users
-                        .entry(info.name.clone())
-                        .or_insert(UserSession {
-                            rate_limiter: RateLimiter::new(self.config.message.clone()),
-                            connections: HashSet::new(),
-                        })
-                        .connections
-                        .insert(user_id);
-
-                    session.user = Some(User {
-                        name: info.name,
-                        uuid: info.uuid,
-                        allow_messages,
-                    });
-
-                    session.addr.do_send(ClientPacket::Success {
-                        reason: SuccessReason::Login,
-                    });
-                }
-                Err(err) => {
-                    info!("Login of user `{}` using JWT failed: {}", user_id, err);
-                    session.addr.do_send(ClientPacket::Error {
-                        message: ClientError::LoginFailed,
-                    });
-                }
-            };
-        } else {
-            info!("User `{}` tried to request not supported JWT", user_id);
-            session.addr.do_send(ClientPacket::Error {
-                message: ClientError::NotSupported,
-            });
-        }
+        let credential = serde_json::json!({ "token": token });
+        self.handle_login(
+            user_id,
+            String::from("jwt"),
+            credential,
+            allow_messages,
+            ctx,
+        );
     }
 }
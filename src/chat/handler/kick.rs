@@ -0,0 +1,56 @@
+use super::ChatServer;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{CloseSession, InternalId, SuccessReason};
+
+use crate::error::*;
+use crate::moderation::Permission;
+use log::*;
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn kick_user(
+        &mut self,
+        user_id: InternalId,
+        to_kick: &Uuid,
+        reason: Option<String>,
+    ) {
+        self.moderate_user(
+            user_id,
+            Permission::Kick,
+            SuccessReason::Kick,
+            |server, issuer| server.kick(Some(issuer), to_kick, reason),
+        );
+    }
+
+    /// Closes every connection of the user on behalf of `issuer`.
+    pub(super) fn kick(
+        &mut self,
+        issuer: Option<Uuid>,
+        to_kick: &Uuid,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let connected = self.connections_of(to_kick).next().is_some();
+        let mut entry = AuditEntry::new(AuditAction::Kick, Some(*to_kick));
+        entry.reason = reason.clone();
+
+        self.moderate(issuer, entry, |moderation| {
+            if moderation.has_permission(to_kick, Permission::Kick) {
+                Err(ClientError::NotPermitted.into())
+            } else if !connected {
+                Err(ClientError::NotConnected.into())
+            } else {
+                Ok(())
+            }
+        })?;
+
+        let code = self.config.moderation.kick_close_code;
+        for (id, session) in self.connections_of(to_kick) {
+            info!("Kicking `{}` of user `{}`.", id, to_kick);
+            session.addr.do_send(CloseSession {
+                code,
+                reason: reason.clone(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use crate::error::*;
+use log::*;
+
+use crate::auth::{LoginRequest, UserInfo};
+use crate::chat::{ChatServer, ClientPacket, InternalId, User};
+use actix::*;
+use std::task::{self, Poll, Waker};
+
+impl ChatServer {
+    /// Logs the client in with the credential if `provider` accepts it.
+    pub(super) fn handle_login(
+        &mut self,
+        user_id: InternalId,
+        provider: String,
+        credential: serde_json::Value,
+        allow_messages: bool,
+        ctx: &mut Context<Self>,
+    ) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        if session.is_logged_in() {
+            info!("User `{}` tried to log in multiple times.", user_id);
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::AlreadyLoggedIn,
+            });
+            return;
+        }
+
+        let Some(auth_provider) = self.providers.get(&provider) else {
+            info!(
+                "User `{}` tried to log in with unknown provider `{}`.",
+                user_id, provider
+            );
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::NotSupported,
+            });
+            return;
+        };
+
+        let mut login = auth_provider.login(LoginRequest {
+            credential,
+            session_hash: session.session_hash.clone(),
+        });
+
+        // Providers which don't have to wait for anything are done right away,
+        // so the login takes effect before the next packet of the client is handled.
+        let mut cx = task::Context::from_waker(Waker::noop());
+        if let Poll::Ready(res) = login.as_mut().poll(&mut cx) {
+            self.finish_login(user_id, &provider, res, allow_messages);
+            return;
+        }
+
+        ctx.spawn(login.into_actor(self).map(move |res, actor, _ctx| {
+            actor.finish_login(user_id, &provider, res, allow_messages);
+        }));
+    }
+
+    fn finish_login(
+        &mut self,
+        user_id: InternalId,
+        provider: &str,
+        res: std::result::Result<UserInfo, ClientError>,
+        allow_messages: bool,
+    ) {
+        // The client may have disconnected or logged in otherwise in the meantime.
+        let Some(session) = self.connections.get(&user_id) else {
+            return;
+        };
+
+        match res {
+            Ok(_) if session.is_logged_in() => {
+                session.addr.do_send(ClientPacket::Error {
+                    message: ClientError::AlreadyLoggedIn,
+                });
+            }
+            Ok(info) => {
+                info!(
+                    "User `{}` logged in with `{}` as `{}` with uuid `{}`",
+                    user_id, provider, info.name, info.uuid
+                );
+                self.login_user(
+                    user_id,
+                    User {
+                        name: info.name,
+                        uuid: info.uuid,
+                        allow_messages,
+                        anonymous: false,
+                    },
+                );
+            }
+            Err(err) => {
+                info!(
+                    "Login of user `{}` with `{}` failed: {}",
+                    user_id, provider, err
+                );
+                session.addr.do_send(ClientPacket::Error { message: err });
+            }
+        }
+    }
+}
@@ -6,7 +6,11 @@ use crate::error::*;
 use log::*;
 
 impl ChatServer {
-    pub(super) fn handle_message(&mut self, user_id: InternalId, content: String) {
+    pub(super) fn handle_message(&mut self, user_id: InternalId, content: String, channel: String) {
+        if !self.check_channel(user_id, &channel) {
+            return;
+        }
+
         if self.check_ratelimit(user_id, content.clone()) {
             return;
         }
@@ -19,17 +23,27 @@ impl ChatServer {
 
             let info = session.user.as_ref().unwrap();
 
-            info!("User `{}` has written `{}`.", user_id, content);
+            info!(
+                "User `{}` has written `{}` in `{}`.",
+                user_id, content, channel
+            );
+            let author_info = UserInfo {
+                name: info.name.clone(),
+                uuid: info.uuid,
+            };
+            self.history
+                .push(channel.clone(), author_info.clone(), content.clone());
+
+            let members = &self.channels[&channel].members;
             let client_packet = ClientPacket::Message {
-                author_info: UserInfo {
-                    name: info.name.clone(),
-                    uuid: info.uuid,
-                },
+                author_info,
                 content,
+                channel,
             };
-            for session in self.connections.values() {
+            for session in members.iter().filter_map(|id| self.connections.get(id)) {
                 session.addr.do_send(client_packet.clone());
             }
+            self.metrics.message_broadcast();
         }
     }
 
@@ -45,8 +59,15 @@ impl ChatServer {
 
         if let Some(sender_session) = self.basic_check(user_id, &content) {
             let sender_info = sender_session.user.as_ref().unwrap();
+            if sender_info.anonymous {
+                info!("Anonymous user `{}` tried to write privately.", user_id);
+                sender_session.addr.do_send(ClientPacket::Error {
+                    message: ClientError::NotPermitted,
+                });
+                return;
+            }
 
-            let receiver_user = match self.users.get(&receiver) {
+            let receiver_user = match self.user_by_name(&receiver) {
                 Some(user) => user,
                 None => {
                     debug!(
@@ -63,7 +84,7 @@ impl ChatServer {
                 .filter_map(|id| self.connections.get(id))
             {
                 match &receiver_session.user {
-                    Some(info) if info.allow_messages => {
+                    Some(info) if info.allow_messages && !info.anonymous => {
                         let client_packet = ClientPacket::PrivateMessage {
                             author_info: UserInfo {
                                 name: sender_info.name.clone(),
@@ -76,6 +97,7 @@ impl ChatServer {
                             user_id, receiver
                         );
                         receiver_session.addr.do_send(client_packet);
+                        self.metrics.private_message_delivered();
                     }
                     _ => {}
                 }
@@ -98,7 +120,11 @@ impl ChatServer {
             .expect("could not find connection");
 
         if let Some(info) = &session.user {
-            if let Err(err) = self.validator.validate(content) {
+            let validator = match &self.anonymous_validator {
+                Some(validator) if info.anonymous => validator,
+                _ => &self.validator,
+            };
+            if let Err(err) = validator.validate(content) {
                 info!("User `{}` tried to send invalid message: {}", user_id, err);
                 if let Error::AxoChat { source } = err {
                     session
@@ -108,10 +134,24 @@ impl ChatServer {
 
                 return None;
             }
-            if self.moderation.is_banned(&info.uuid) {
+            if let Some(ban) = self.moderation.get_ban(&info.uuid) {
                 info!("User `{}` tried to send message while banned", user_id);
                 session.addr.do_send(ClientPacket::Error {
-                    message: ClientError::Banned,
+                    message: ClientError::Banned {
+                        reason: ban.reason.clone(),
+                        until: ban.until,
+                    },
+                });
+
+                return None;
+            }
+            if let Some(mute) = self.moderation.get_mute(&info.uuid) {
+                info!("User `{}` tried to send message while muted", user_id);
+                session.addr.do_send(ClientPacket::Error {
+                    message: ClientError::Muted {
+                        reason: mute.reason.clone(),
+                        until: mute.until,
+                    },
                 });
 
                 return None;
@@ -134,8 +174,9 @@ impl ChatServer {
             .expect("could not find connection");
 
         if let Some(user) = &session.user {
-            let user = self.users.get_mut(&user.name).unwrap();
+            let user = self.users.get_mut(&user.uuid).unwrap();
             if user.rate_limiter.check_new_message(message) {
+                self.metrics.rate_limited();
                 info!(
                     "User `{}` tried to send message, but was rate limited.",
                     user_id
@@ -1,12 +1,36 @@
+mod admin;
+mod alts;
+mod anonymous;
+mod audit;
 mod ban;
+mod broadcast;
+mod channel;
 mod count;
+mod history;
+mod ip_ban;
 mod jwt;
+mod kick;
+mod login;
 mod message;
 mod mojang;
+mod mute;
+mod reload;
+mod role;
 
-use super::{ChatServer, ClientPacket, ServerPacket, ServerPacketId};
+use super::{
+    ChatServer, ClientPacket, InternalId, ServerPacket, ServerPacketId, SuccessReason, User,
+    UserSession,
+};
+use crate::audit::{AuditEntry, AuditResult};
+use crate::config::MsgConfig;
+use crate::error::*;
+use crate::message::RateLimiter;
+use crate::moderation::{Moderation, Permission};
+use log::*;
 
 use actix::*;
+use std::collections::HashSet;
+use uuid::Uuid;
 
 impl Handler<ServerPacketId> for ChatServer {
     type Result = ();
@@ -16,6 +40,7 @@ impl Handler<ServerPacketId> for ChatServer {
         ServerPacketId { user_id, packet }: ServerPacketId,
         ctx: &mut Context<Self>,
     ) {
+        self.metrics.packet_received(packet.kind());
         match packet {
             ServerPacket::RequestMojangInfo => {
                 self.handle_request_mojang_info(user_id);
@@ -23,6 +48,13 @@ impl Handler<ServerPacketId> for ChatServer {
             ServerPacket::LoginMojang(info) => {
                 self.login_mojang(user_id, info, ctx);
             }
+            ServerPacket::Login {
+                provider,
+                credential,
+                allow_messages,
+            } => {
+                self.handle_login(user_id, provider, credential, allow_messages, ctx);
+            }
             ServerPacket::RequestJWT => {
                 self.handle_request_jwt(user_id);
             }
@@ -30,21 +62,229 @@ impl Handler<ServerPacketId> for ChatServer {
                 token,
                 allow_messages,
             } => {
-                self.handle_login_jwt(user_id, &token, allow_messages);
+                self.handle_login_jwt(user_id, token, allow_messages, ctx);
+            }
+            ServerPacket::LoginAnonymous => {
+                self.handle_login_anonymous(user_id);
+            }
+            ServerPacket::Message { content, channel } => {
+                self.handle_message(user_id, content, channel);
             }
-            ServerPacket::Message { content } => self.handle_message(user_id, content),
             ServerPacket::PrivateMessage { receiver, content } => {
                 self.handle_private_message(user_id, receiver, content);
             }
-            ServerPacket::BanUser { user } => {
-                self.ban_user(user_id, &user);
+            ServerPacket::BanUser {
+                user,
+                reason,
+                duration,
+            } => {
+                self.ban_user(user_id, &user, reason, duration);
             }
             ServerPacket::UnbanUser { user } => {
                 self.unban_user(user_id, &user);
             }
+            ServerPacket::BanIp {
+                ip_or_cidr,
+                reason,
+                duration,
+                force,
+            } => {
+                self.ban_ip(user_id, ip_or_cidr, reason, duration, force);
+            }
+            ServerPacket::UnbanIp { ip_or_cidr } => {
+                self.unban_ip(user_id, ip_or_cidr);
+            }
+            ServerPacket::RequestAlts { user } => {
+                self.handle_request_alts(user_id, &user);
+            }
+            ServerPacket::MuteUser {
+                user,
+                reason,
+                duration,
+            } => {
+                self.mute_user(user_id, &user, reason, duration);
+            }
+            ServerPacket::UnmuteUser { user } => {
+                self.unmute_user(user_id, &user);
+            }
+            ServerPacket::KickUser { user, reason } => {
+                self.kick_user(user_id, &user, reason);
+            }
+            ServerPacket::GrantRole { user, role } => {
+                self.grant_role(user_id, &user, &role);
+            }
+            ServerPacket::RevokeRole { user, role } => {
+                self.revoke_role(user_id, &user, &role);
+            }
+            ServerPacket::Broadcast { content } => {
+                self.handle_broadcast(user_id, content);
+            }
+            ServerPacket::RequestAuditLog { target, limit } => {
+                self.handle_request_audit_log(user_id, target, limit);
+            }
             ServerPacket::RequestUserCount => {
                 self.send_user_count(user_id);
             }
+            ServerPacket::JoinChannel { name } => {
+                self.handle_join_channel(user_id, &name);
+            }
+            ServerPacket::LeaveChannel { name } => {
+                self.handle_leave_channel(user_id, &name);
+            }
+            ServerPacket::ListChannels => {
+                self.handle_list_channels(user_id);
+            }
+            ServerPacket::RequestHistory {
+                channel,
+                before,
+                limit,
+            } => {
+                self.handle_request_history(user_id, channel, before, limit);
+            }
+            ServerPacket::ReloadConfig => {
+                self.handle_reload_config(user_id);
+            }
         }
     }
 }
+
+impl ChatServer {
+    /// Associates the connection `user_id` with `user` and confirms the login.
+    fn login_user(&mut self, user_id: InternalId, user: User) {
+        let msg_config = self.msg_config(user.anonymous);
+        let ip = self
+            .connections
+            .get(&user_id)
+            .and_then(|session| session.ip);
+        if !user.anonymous
+            && let Err(err) = self.moderation.record_login(&user.uuid, &user.name, ip)
+        {
+            warn!("Could not record login of user `{}`: {}", user.uuid, err);
+        }
+
+        let user_session = self.users.entry(user.uuid).or_insert(UserSession {
+            name: user.name.clone(),
+            rate_limiter: RateLimiter::new(msg_config),
+            anonymous: user.anonymous,
+            connections: HashSet::new(),
+        });
+        user_session.connections.insert(user_id);
+        let previous_name = std::mem::replace(&mut user_session.name, user.name.clone());
+        if previous_name != user.name {
+            self.unindex_name(&previous_name, &user.uuid);
+        }
+        self.user_names
+            .insert(user.name.to_ascii_lowercase(), user.uuid);
+
+        let session = self
+            .connections
+            .get_mut(&user_id)
+            .expect("could not find connection");
+        session.user = Some(user);
+
+        session.addr.do_send(ClientPacket::Success {
+            reason: SuccessReason::Login,
+        });
+        self.send_login_history(user_id);
+    }
+
+    /// Returns the message limits of anonymous or regular users.
+    fn msg_config(&self, anonymous: bool) -> MsgConfig {
+        match &self.config.auth {
+            Some(auth) if anonymous => auth.anonymous_message.clone(),
+            _ => self.config.message.clone(),
+        }
+    }
+
+    /// Returns the uuid of the user logged in on connection `user_id`
+    /// if the user has the permission; otherwise an error is sent to the connection.
+    fn check_permission(&self, user_id: InternalId, permission: Permission) -> Option<Uuid> {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+
+        if let Some(info) = &session.user {
+            if self.moderation.has_permission(&info.uuid, permission) {
+                Some(info.uuid)
+            } else {
+                info!(
+                    "`{}` tried to use {:?} without permission.",
+                    user_id, permission
+                );
+                session.addr.do_send(ClientPacket::Error {
+                    message: ClientError::NotPermitted,
+                });
+                None
+            }
+        } else {
+            info!("`{}` is not logged in.", user_id);
+            session.addr.do_send(ClientPacket::Error {
+                message: ClientError::NotLoggedIn,
+            });
+            None
+        }
+    }
+
+    /// Runs a moderation `action` on behalf of the user of connection `user_id`
+    /// if the user has the permission and answers with a success or the error.
+    /// The action receives the uuid of the acting user and has to record itself
+    /// in the audit log, usually with [`ChatServer::moderate`].
+    fn moderate_user(
+        &mut self,
+        user_id: InternalId,
+        permission: Permission,
+        reason: SuccessReason,
+        action: impl FnOnce(&mut Self, Uuid) -> Result<()>,
+    ) {
+        let Some(issuer) = self.check_permission(user_id, permission) else {
+            return;
+        };
+        let res = action(self, issuer);
+        self.reply(user_id, reason, res);
+    }
+
+    /// Runs a moderation `action` on behalf of `actor` and records it in the audit log.
+    /// Actions taken through the admin API have no actor.
+    fn moderate(
+        &mut self,
+        actor: Option<Uuid>,
+        mut entry: AuditEntry,
+        action: impl FnOnce(&mut Moderation) -> Result<()>,
+    ) -> Result<()> {
+        entry.actor = actor;
+        let res = action(&mut self.moderation);
+        let target = entry.target.unwrap_or_default();
+        match &res {
+            Ok(()) => {
+                info!("{:?} of user `{}` successful.", entry.action, target);
+            }
+            Err(Error::AxoChat { source }) => {
+                info!("{:?} of user `{}` failed: {}", entry.action, target, source);
+                entry.result = AuditResult::Failure(source.to_string());
+            }
+            Err(err) => {
+                warn!("{:?} of user `{}` failed: {}", entry.action, target, err);
+                entry.result = AuditResult::Failure(err.to_string());
+            }
+        }
+        self.audit_log.record(&entry);
+        res
+    }
+
+    /// Answers the connection `user_id` with a success if `res` is ok or with the error.
+    fn reply(&self, user_id: InternalId, reason: SuccessReason, res: Result<()>) {
+        let session = self
+            .connections
+            .get(&user_id)
+            .expect("could not find connection");
+        let packet = match res {
+            Ok(()) => ClientPacket::Success { reason },
+            Err(Error::AxoChat { source }) => ClientPacket::Error { message: source },
+            Err(_) => ClientPacket::Error {
+                message: ClientError::Internal,
+            },
+        };
+        session.addr.do_send(packet);
+    }
+}
@@ -1,15 +1,7 @@
-use crate::error::*;
-use log::*;
+use crate::chat::{ChatServer, ClientPacket, InternalId, User};
 
-use crate::chat::{ChatServer, ClientPacket, InternalId, SuccessReason, User, UserSession};
-use crate::message::RateLimiter;
-use std::collections::HashSet;
-
-use crate::auth::authenticate;
 use actix::*;
 use rand::RngCore;
-use std::str::FromStr;
-use uuid::Uuid;
 
 impl ChatServer {
     pub(super) fn handle_request_mojang_info(&mut self, user_id: InternalId) {
@@ -37,92 +29,13 @@ impl ChatServer {
         info: User,
         ctx: &mut Context<Self>,
     ) {
-        let session = self
-            .connections
-            .get(&user_id)
-            .expect("could not find connection");
-
-        if session.is_logged_in() {
-            info!("User `{}` tried to log in multiple times.", user_id);
-            session.addr.do_send(ClientPacket::Error {
-                message: ClientError::AlreadyLoggedIn,
-            });
-            return;
-        }
-
-        if let Some(session_hash) = &session.session_hash {
-            // Clone the session_hash and user_id before moving them into the async block
-            let session_hash = session_hash.clone();
-            let name = info.name.clone();
-            let uuid = info.uuid;
-            let userid_for_closure = user_id;
-            let session_addr = session.addr.clone();
-
-            // Spawn a Future that performs the authentication
-            ctx.spawn(
-                async move { authenticate(&name, &session_hash).await }
-                    .into_actor(self)
-                    .then(move |res, actor, _ctx| {
-                        match res {
-                            Ok(mojang_info)
-                                if Uuid::from_str(&mojang_info.id)
-                                    .expect("got invalid uuid from mojang :()")
-                                    == uuid =>
-                            {
-                                info!(
-                                    "User `{}` has uuid `{}` and username `{}`",
-                                    userid_for_closure, mojang_info.id, mojang_info.name
-                                );
-
-                                if let Some(session) =
-                                    actor.connections.get_mut(&userid_for_closure)
-                                {
-                                    actor
-                                        .users
-                                        .entry(info.name.clone())
-                                        .or_insert(UserSession {
-                                            rate_limiter: RateLimiter::new(
-                                                actor.config.message.clone(),
-                                            ),
-                                            connections: HashSet::new(),
-                                        })
-                                        .connections
-                                        .insert(userid_for_closure);
-
-                                    session.user = Some(info);
-
-                                    // Use standalone send_message function
-                                    session_addr.do_send(ClientPacket::Success {
-                                        reason: SuccessReason::Login,
-                                    });
-                                }
-                            }
-                            Ok(_) => {
-                                session_addr.do_send(ClientPacket::Error {
-                                    message: ClientError::InvalidId,
-                                });
-                            }
-                            Err(err) => {
-                                warn!(
-                                    "Could not authenticate user `{}`: {}",
-                                    userid_for_closure, err
-                                );
-                                session_addr.do_send(ClientPacket::Error {
-                                    message: ClientError::LoginFailed,
-                                });
-                            }
-                        }
-                        fut::ready(())
-                    }),
-            );
-        } else {
-            info!(
-                "User `{}` did not request mojang info, but tried to log in.",
-                user_id
-            );
-            session.addr.do_send(ClientPacket::Error {
-                message: ClientError::MojangRequestMissing,
-            });
-        }
+        let credential = serde_json::json!({ "name": info.name, "uuid": info.uuid });
+        self.handle_login(
+            user_id,
+            String::from("mojang"),
+            credential,
+            info.allow_messages,
+            ctx,
+        );
     }
 }
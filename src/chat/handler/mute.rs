@@ -0,0 +1,55 @@
+use super::ChatServer;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{InternalId, SuccessReason};
+use crate::config::WDuration;
+use crate::error::*;
+use crate::moderation::Permission;
+
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn mute_user(
+        &mut self,
+        user_id: InternalId,
+        to_mute: &Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    ) {
+        self.moderate_user(
+            user_id,
+            Permission::Mute,
+            SuccessReason::Mute,
+            |server, issuer| server.mute(Some(issuer), to_mute, reason, duration),
+        );
+    }
+
+    pub(super) fn mute(
+        &mut self,
+        issuer: Option<Uuid>,
+        to_mute: &Uuid,
+        reason: Option<String>,
+        duration: Option<WDuration>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::Mute, Some(*to_mute));
+        entry.reason = reason.clone();
+        entry.details = duration.map(|duration| humantime::format_duration(*duration).to_string());
+
+        self.moderate(issuer, entry, |moderation| {
+            moderation.mute(to_mute, issuer, reason, duration.map(|d| *d))
+        })
+    }
+
+    pub(super) fn unmute_user(&mut self, user_id: InternalId, to_unmute: &Uuid) {
+        self.moderate_user(
+            user_id,
+            Permission::Mute,
+            SuccessReason::Unmute,
+            |server, issuer| server.unmute(Some(issuer), to_unmute),
+        );
+    }
+
+    pub(super) fn unmute(&mut self, issuer: Option<Uuid>, to_unmute: &Uuid) -> Result<()> {
+        let entry = AuditEntry::new(AuditAction::Unmute, Some(*to_unmute));
+        self.moderate(issuer, entry, |moderation| moderation.unmute(to_unmute))
+    }
+}
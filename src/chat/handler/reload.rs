@@ -0,0 +1,146 @@
+use crate::error::*;
+use log::*;
+
+use crate::auth::{self, Authenticator};
+use crate::chat::{
+    Channel, ChatServer, ClientPacket, GLOBAL_CHANNEL, InternalId, ReloadConfig, ReloadModeration,
+};
+use crate::config;
+use crate::message::MessageValidator;
+use crate::moderation::Permission;
+use actix::*;
+use std::collections::{HashSet, hash_map::Entry};
+use std::rc::Rc;
+
+impl Handler<ReloadModeration> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ReloadModeration, _ctx: &mut Context<Self>) {
+        for user in self.moderation.reload_if_changed() {
+            self.notify_banned(&user);
+        }
+    }
+}
+
+impl Handler<ReloadConfig> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: ReloadConfig, _ctx: &mut Context<Self>) {
+        info!("Reloading configuration.");
+        if let Err(err) = self.reload_config() {
+            warn!("Keeping current configuration, reload failed: {}", err);
+        }
+    }
+}
+
+impl ChatServer {
+    pub(super) fn handle_reload_config(&mut self, user_id: InternalId) {
+        if self
+            .check_permission(user_id, Permission::ReloadConfig)
+            .is_none()
+        {
+            return;
+        }
+
+        info!("User `{}` reloads the configuration.", user_id);
+        let packet = match self.reload_config() {
+            Ok(restart_required) => ClientPacket::ConfigReloaded {
+                restart_required: restart_required.into_iter().map(String::from).collect(),
+            },
+            Err(err) => {
+                warn!("Keeping current configuration, reload failed: {}", err);
+                ClientPacket::Error {
+                    message: ClientError::InvalidConfig(err.to_string()),
+                }
+            }
+        };
+        self.connections
+            .get(&user_id)
+            .expect("could not find connection")
+            .addr
+            .do_send(packet);
+    }
+
+    /// Reads the configuration file again and applies every setting
+    /// which can be changed while the server is running.
+    /// Returns the names of the changed settings which require a restart.
+    fn reload_config(&mut self) -> Result<Vec<&'static str>> {
+        let new = config::reload_config()?;
+        let authenticator = new
+            .auth
+            .as_ref()
+            .map(Authenticator::new)
+            .transpose()?
+            .map(Rc::new);
+
+        let restart_required = self.config.update(new);
+        for name in &restart_required {
+            warn!("The change of `{}` requires a restart.", name);
+        }
+
+        self.authenticator = authenticator;
+        self.providers = auth::providers(
+            &self.config,
+            self.authenticator.as_ref(),
+            &self.metrics,
+            &mut self.breakers,
+        );
+        self.validator = MessageValidator::new(self.config.message.clone());
+        self.anonymous_validator = self
+            .config
+            .auth
+            .as_ref()
+            .filter(|auth| auth.allow_anonymous)
+            .map(|auth| MessageValidator::new(auth.anonymous_message.clone()));
+
+        let (regular, anonymous) = (self.msg_config(false), self.msg_config(true));
+        for session in self.users.values_mut() {
+            let cfg = if session.anonymous {
+                &anonymous
+            } else {
+                &regular
+            };
+            session.rate_limiter.set_config(cfg.clone());
+        }
+
+        self.update_channels();
+
+        for user in self.moderation.set_config(self.config.moderation.clone()) {
+            self.notify_banned(&user);
+        }
+
+        Ok(restart_required)
+    }
+
+    /// Adds, updates and removes channels according to the configuration.
+    /// The global channel is never removed.
+    fn update_channels(&mut self) {
+        let configured: HashSet<&str> = self
+            .config
+            .channels
+            .iter()
+            .map(|cfg| cfg.name.as_str())
+            .collect();
+        self.channels.retain(|name, _| {
+            let keep = name == GLOBAL_CHANNEL || configured.contains(name.as_str());
+            if !keep {
+                info!("Removed channel `{}`.", name);
+            }
+            keep
+        });
+
+        for cfg in &self.config.channels {
+            match self.channels.entry(cfg.name.clone()) {
+                Entry::Occupied(mut entry) => {
+                    let channel = entry.get_mut();
+                    channel.description = cfg.description.clone();
+                    channel.default_joined = cfg.default_joined;
+                }
+                Entry::Vacant(entry) => {
+                    info!("Added channel `{}`.", cfg.name);
+                    entry.insert(Channel::new(cfg));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use super::ChatServer;
+use crate::audit::{AuditAction, AuditEntry};
+use crate::chat::{InternalId, SuccessReason};
+use crate::error::*;
+use crate::moderation::Permission;
+
+use uuid::Uuid;
+
+impl ChatServer {
+    pub(super) fn grant_role(&mut self, user_id: InternalId, receiver: &Uuid, role: &str) {
+        self.moderate_user(
+            user_id,
+            Permission::ManageRoles,
+            SuccessReason::GrantRole,
+            |server, issuer| server.grant(Some(issuer), receiver, role),
+        );
+    }
+
+    /// Assigns the role to the user on behalf of `issuer`.
+    pub(super) fn grant(
+        &mut self,
+        issuer: Option<Uuid>,
+        receiver: &Uuid,
+        role: &str,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::GrantRole, Some(*receiver));
+        entry.details = Some(role.to_string());
+        self.moderate(issuer, entry, |moderation| {
+            moderation.grant_role(issuer.as_ref(), receiver, role)
+        })
+    }
+
+    pub(super) fn revoke_role(&mut self, user_id: InternalId, receiver: &Uuid, role: &str) {
+        self.moderate_user(
+            user_id,
+            Permission::ManageRoles,
+            SuccessReason::RevokeRole,
+            |server, issuer| server.revoke(Some(issuer), receiver, role),
+        );
+    }
+
+    /// Removes the role from the user on behalf of `issuer`.
+    pub(super) fn revoke(
+        &mut self,
+        issuer: Option<Uuid>,
+        receiver: &Uuid,
+        role: &str,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditAction::RevokeRole, Some(*receiver));
+        entry.details = Some(role.to_string());
+        self.moderate(issuer, entry, |moderation| {
+            moderation.revoke_role(issuer.as_ref(), receiver, role)
+        })
+    }
+}
@@ -0,0 +1,48 @@
+use super::ChatServer;
+use log::*;
+
+use actix::*;
+use serde::Serialize;
+
+/// Answers as soon as the `ChatServer` processes it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Ping;
+
+impl Handler<Ping> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Context<Self>) {}
+}
+
+/// Checks whether the `ChatServer` can serve clients.
+#[derive(Message)]
+#[rtype(result = "Readiness")]
+pub struct CheckReadiness;
+
+#[derive(Serialize, MessageResponse)]
+pub struct Readiness {
+    /// Can every moderation file be read?
+    pub moderation: bool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.moderation
+    }
+}
+
+impl Handler<CheckReadiness> for ChatServer {
+    type Result = Readiness;
+
+    fn handle(&mut self, _msg: CheckReadiness, _ctx: &mut Context<Self>) -> Readiness {
+        let moderation = match self.moderation.check_files() {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("Moderation files are not readable: {}", err);
+                false
+            }
+        };
+        Readiness { moderation }
+    }
+}
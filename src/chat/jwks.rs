@@ -0,0 +1,17 @@
+use super::ChatServer;
+
+use actix::*;
+
+/// Returns the public keys tokens are verified with as a JSON Web Key Set,
+/// if authentication is configured.
+#[derive(Message)]
+#[rtype(result = "Option<serde_json::Value>")]
+pub struct GetJwks;
+
+impl Handler<GetJwks> for ChatServer {
+    type Result = Option<serde_json::Value>;
+
+    fn handle(&mut self, _msg: GetJwks, _ctx: &mut Context<Self>) -> Self::Result {
+        self.authenticator.as_ref().map(|auth| auth.jwks())
+    }
+}
@@ -1,11 +1,15 @@
+pub mod admin;
 mod connect;
 mod handler;
+pub mod health;
 mod id;
+pub mod jwks;
 mod session;
+pub mod shutdown;
 
 pub use id::*;
 
-use crate::config::Config;
+use crate::config::{ChannelConfig, Config, NetConfig, WDuration};
 use crate::error::*;
 use log::*;
 
@@ -14,21 +18,64 @@ use actix_web::{HttpRequest, HttpResponse, web};
 use actix_web_actors::ws;
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{Authenticator, UserInfo};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::auth::{AuthProvider, Authenticator, Breakers, UserInfo};
+use crate::history::{History, HistoryEntry};
+use crate::ip::IpRange;
+use crate::limit::ConnectionLimiter;
 use crate::message::{MessageValidator, RateLimiter};
-use crate::moderation::Moderation;
+use crate::metrics::Metrics;
+use crate::moderation::{Alt, Moderation};
+use crate::proxy;
 use rand::SeedableRng;
 use rand_hc::Hc128Rng;
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
 use uuid::Uuid;
 
 pub async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<ChatServer>>,
+    metrics: web::Data<Metrics>,
+    net: web::Data<NetConfig>,
+    limiter: web::Data<ConnectionLimiter>,
 ) -> actix_web::Result<HttpResponse> {
+    let ip = proxy::client_ip(&req, &net.trusted_proxies, net.forwarded_header);
+    if let Some(ip) = ip {
+        match srv.send(CheckAddress { ip }).await {
+            Ok(None) => {}
+            Ok(Some(ban)) => {
+                info!("Rejected connection from banned address {}.", ip);
+                return Ok(HttpResponse::Forbidden().json(ban));
+            }
+            Err(err) => {
+                warn!("Could not check address {}: {}", ip, err);
+                return Ok(HttpResponse::InternalServerError().finish());
+            }
+        }
+    }
+
+    // Banned addresses are rejected first, so they don't use up the connection limits.
+    let permit = match limiter.into_inner().acquire(ip) {
+        Ok(permit) => permit,
+        Err(err) => {
+            info!("Rejected connection from {:?}: {}", ip, err);
+            metrics.connection_rejected(err.kind());
+            return Ok(HttpResponse::TooManyRequests().body(err.to_string()));
+        }
+    };
+
     ws::start(
-        session::Session::new(InternalId::new(0), srv.get_ref().clone()),
+        session::Session::new(
+            InternalId::new(0),
+            srv.get_ref().clone(),
+            metrics.into_inner(),
+            &net,
+            permit,
+        ),
         &req,
         stream,
     )
@@ -36,40 +83,189 @@ pub async fn chat_route(
 
 pub struct ChatServer {
     connections: HashMap<InternalId, SessionState>,
-    users: HashMap<String, UserSession>,
+    users: HashMap<Uuid, UserSession>,
+    /// Maps the lowercase names of the logged in users to their uuids.
+    user_names: HashMap<String, Uuid>,
+    channels: HashMap<String, Channel>,
 
     rng: rand_hc::Hc128Rng,
-    authenticator: Option<Authenticator>,
+    authenticator: Option<Rc<Authenticator>>,
+    /// The providers clients can log in with, by name.
+    providers: HashMap<String, Rc<dyn AuthProvider>>,
+    breakers: Breakers,
     validator: MessageValidator,
+    anonymous_validator: Option<MessageValidator>,
     moderation: Moderation,
+    audit_log: AuditLog,
+    history: History,
+    metrics: Arc<Metrics>,
     config: Config,
 
     current_internal_user_id: u64,
 }
 
 impl ChatServer {
-    pub fn new(config: Config) -> ChatServer {
+    pub fn new(config: Config, metrics: Arc<Metrics>) -> ChatServer {
+        let mut channels: HashMap<String, Channel> = config
+            .channels
+            .iter()
+            .map(|cfg| (cfg.name.clone(), Channel::new(cfg)))
+            .collect();
+        channels
+            .entry(GLOBAL_CHANNEL.to_string())
+            .or_insert_with(|| {
+                Channel::new(&ChannelConfig {
+                    name: GLOBAL_CHANNEL.to_string(),
+                    description: String::from("The global chat."),
+                    default_joined: true,
+                })
+            });
+
+        let authenticator = config.auth.as_ref().map(|auth| {
+            Rc::new(Authenticator::new(auth).expect("could not initialize authenticator"))
+        });
+        let mut breakers = Breakers::default();
+        let providers =
+            crate::auth::providers(&config, authenticator.as_ref(), &metrics, &mut breakers);
+
         ChatServer {
             connections: HashMap::new(),
             users: HashMap::new(),
+            user_names: HashMap::new(),
+            channels,
 
             rng: Hc128Rng::from_rng(&mut rand::rng()),
-            authenticator: config
+            authenticator,
+            providers,
+            breakers,
+            validator: MessageValidator::new(config.message.clone()),
+            anonymous_validator: config
                 .auth
                 .as_ref()
-                .map(|auth| Authenticator::new(auth).expect("could not initialize authenticator")),
-            validator: MessageValidator::new(config.message.clone()),
+                .filter(|auth| auth.allow_anonymous)
+                .map(|auth| MessageValidator::new(auth.anonymous_message.clone())),
             moderation: Moderation::new(config.moderation.clone())
                 .expect("could not start moderation"),
+            audit_log: AuditLog::new(
+                config.moderation.audit_log.clone(),
+                config.moderation.audit_log_tail,
+            )
+            .expect("could not open audit log"),
+            history: History::new(&config.message).expect("could not load message history"),
+            metrics,
             config,
 
             current_internal_user_id: 0,
         }
     }
+
+    /// Returns the logged in user with this name, ignoring case.
+    /// If several users claim the name, the one who logged in last is returned.
+    fn user_by_name(&self, name: &str) -> Option<&UserSession> {
+        let uuid = self.user_names.get(&name.to_ascii_lowercase())?;
+        self.users.get(uuid)
+    }
+
+    /// Removes `name` from the name index if it refers to `uuid`.
+    /// Another logged in user with the same name takes its place.
+    fn unindex_name(&mut self, name: &str, uuid: &Uuid) {
+        let key = name.to_ascii_lowercase();
+        if self.user_names.get(&key) != Some(uuid) {
+            return;
+        }
+        match self
+            .users
+            .iter()
+            .find(|(other, session)| *other != uuid && session.name.eq_ignore_ascii_case(name))
+        {
+            Some((other, _)) => {
+                self.user_names.insert(key, *other);
+            }
+            None => {
+                self.user_names.remove(&key);
+            }
+        }
+    }
+
+    /// Returns every connection the user with `uuid` is logged in on.
+    fn connections_of<'a>(
+        &'a self,
+        uuid: &'a Uuid,
+    ) -> impl Iterator<Item = (InternalId, &'a SessionState)> + 'a {
+        self.connections.iter().filter_map(move |(id, session)| {
+            session
+                .user
+                .as_ref()
+                .filter(|user| user.uuid == *uuid)
+                .map(|_| (*id, session))
+        })
+    }
 }
 
 impl Actor for ChatServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(
+            *self.config.moderation.expiry_check_interval,
+            |actor, _ctx| {
+                match actor.moderation.lift_expired_bans() {
+                    Ok(users) => {
+                        for user in users {
+                            info!("Ban of user `{}` expired.", user);
+                        }
+                    }
+                    Err(err) => warn!("Could not lift expired bans: {}", err),
+                }
+                match actor.moderation.lift_expired_mutes() {
+                    Ok(users) => {
+                        for user in users {
+                            info!("Mute of user `{}` expired.", user);
+                        }
+                    }
+                    Err(err) => warn!("Could not lift expired mutes: {}", err),
+                }
+                match actor.moderation.lift_expired_ip_bans() {
+                    Ok(ranges) => {
+                        for range in ranges {
+                            info!("Ban of `{}` expired.", range);
+                        }
+                    }
+                    Err(err) => warn!("Could not lift expired IP bans: {}", err),
+                }
+                match actor.moderation.prune_addresses() {
+                    Ok(0) => {}
+                    Ok(count) => info!("Forgot {} old addresses.", count),
+                    Err(err) => warn!("Could not forget old addresses: {}", err),
+                }
+            },
+        );
+
+        if let Some(interval) = self.config.moderation.reload_interval {
+            ctx.run_interval(*interval, |_actor, ctx| {
+                ctx.notify(ReloadModeration);
+            });
+        }
+
+        #[cfg(unix)]
+        {
+            use actix_rt::signal::unix::{SignalKind, signal};
+
+            let addr = ctx.address();
+            actix::spawn(async move {
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(err) => {
+                        warn!("Could not listen for SIGHUP: {}", err);
+                        return;
+                    }
+                };
+                while hangup.recv().await.is_some() {
+                    addr.do_send(ReloadConfig);
+                }
+            });
+        }
+    }
 }
 
 impl Handler<Disconnect> for ChatServer {
@@ -77,23 +273,30 @@ impl Handler<Disconnect> for ChatServer {
 
     fn handle(&mut self, msg: Disconnect, _ctx: &mut Context<Self>) {
         info!("User `{}` disconnected.", msg.id);
-        if let Some(session) = self.connections.remove(&msg.id) {
-            if let Some(info) = session.user {
-                let user_session = self
-                    .users
-                    .get_mut(&info.name)
-                    .expect("the ids should still exist here");
-                user_session.connections.remove(&msg.id);
-                if user_session.connections.is_empty() {
-                    self.users.remove(&info.name);
-                }
+        for channel in self.channels.values_mut() {
+            channel.members.remove(&msg.id);
+        }
+        if let Some(session) = self.connections.remove(&msg.id)
+            && let Some(info) = session.user
+        {
+            let user_session = self
+                .users
+                .get_mut(&info.uuid)
+                .expect("the ids should still exist here");
+            user_session.connections.remove(&msg.id);
+            if user_session.connections.is_empty()
+                && let Some(user_session) = self.users.remove(&info.uuid)
+            {
+                self.unindex_name(&user_session.name, &info.uuid);
             }
         }
     }
 }
 
 struct SessionState {
-    addr: Recipient<ClientPacket>,
+    addr: Addr<session::Session>,
+    /// The address of the peer, if it is known.
+    ip: Option<IpAddr>,
     session_hash: Option<String>,
     user: Option<User>,
 }
@@ -105,16 +308,68 @@ impl SessionState {
 }
 
 struct UserSession {
+    /// The name the user last logged in with.
+    name: String,
     rate_limiter: RateLimiter,
+    /// Is this a guest which logged in anonymously?
+    anonymous: bool,
     connections: HashSet<InternalId>,
 }
 
+/// The name of the channel every client can write to by default.
+const GLOBAL_CHANNEL: &str = "global";
+
+fn global_channel() -> String {
+    GLOBAL_CHANNEL.to_string()
+}
+
+struct Channel {
+    description: String,
+    default_joined: bool,
+    members: HashSet<InternalId>,
+}
+
+impl Channel {
+    fn new(cfg: &ChannelConfig) -> Channel {
+        Channel {
+            description: cfg.description.clone(),
+            default_joined: cfg.default_joined,
+            members: HashSet::new(),
+        }
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 struct Disconnect {
     id: InternalId,
 }
 
+/// Closes the WebSocket connection of a session.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct CloseSession {
+    code: u16,
+    reason: Option<String>,
+}
+
+/// Returns the `Banned` packet for connections from `ip` if it is banned.
+#[derive(Message)]
+#[rtype(result = "Option<ClientPacket>")]
+struct CheckAddress {
+    ip: IpAddr,
+}
+
+/// Reloads the moderation files if they changed.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ReloadModeration;
+
+/// Reads the configuration file again and applies it.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ReloadConfig;
+
 /// A clientbound packet
 #[derive(Message, Serialize, Clone)]
 #[rtype(result = "()")]
@@ -129,6 +384,7 @@ enum ClientPacket {
     Message {
         author_info: UserInfo,
         content: String,
+        channel: String,
     },
     PrivateMessage {
         author_info: UserInfo,
@@ -138,6 +394,36 @@ enum ClientPacket {
         connections: u32,
         logged_in: u32,
     },
+    Banned {
+        reason: Option<String>,
+        until: Option<u64>,
+    },
+    Announcement {
+        content: String,
+    },
+    AuditLog {
+        entries: Vec<AuditEntry>,
+    },
+    Alts {
+        user: Uuid,
+        alts: Vec<Alt>,
+    },
+    Channels {
+        channels: Vec<ChannelInfo>,
+    },
+    History {
+        channel: String,
+        messages: Vec<HistoryEntry>,
+    },
+    ServerShutdown {
+        reason: String,
+        /// The amount of seconds after which clients may reconnect.
+        reconnect_after: Option<u64>,
+    },
+    ConfigReloaded {
+        /// The changed settings which only take effect after a restart.
+        restart_required: Vec<String>,
+    },
     Success {
         reason: SuccessReason,
     },
@@ -153,13 +439,133 @@ enum ClientPacket {
 enum ServerPacket {
     RequestMojangInfo,
     LoginMojang(User),
-    LoginJWT { token: String, allow_messages: bool },
+    Login {
+        provider: String,
+        credential: serde_json::Value,
+        #[serde(default)]
+        allow_messages: bool,
+    },
+    LoginJWT {
+        token: String,
+        allow_messages: bool,
+    },
+    LoginAnonymous,
     RequestJWT,
-    Message { content: String },
-    PrivateMessage { receiver: String, content: String },
-    BanUser { user: Uuid },
-    UnbanUser { user: Uuid },
+    Message {
+        content: String,
+        #[serde(default = "global_channel")]
+        channel: String,
+    },
+    PrivateMessage {
+        receiver: String,
+        content: String,
+    },
+    BanUser {
+        user: Uuid,
+        #[serde(default)]
+        reason: Option<String>,
+        #[serde(default)]
+        duration: Option<WDuration>,
+    },
+    UnbanUser {
+        user: Uuid,
+    },
+    BanIp {
+        ip_or_cidr: IpRange,
+        #[serde(default)]
+        reason: Option<String>,
+        #[serde(default)]
+        duration: Option<WDuration>,
+        #[serde(default)]
+        force: bool,
+    },
+    UnbanIp {
+        ip_or_cidr: IpRange,
+    },
+    RequestAlts {
+        user: Uuid,
+    },
+    MuteUser {
+        user: Uuid,
+        #[serde(default)]
+        reason: Option<String>,
+        #[serde(default)]
+        duration: Option<WDuration>,
+    },
+    UnmuteUser {
+        user: Uuid,
+    },
+    KickUser {
+        user: Uuid,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    GrantRole {
+        user: Uuid,
+        role: String,
+    },
+    RevokeRole {
+        user: Uuid,
+        role: String,
+    },
+    Broadcast {
+        content: String,
+    },
+    RequestAuditLog {
+        #[serde(default)]
+        target: Option<Uuid>,
+        limit: usize,
+    },
     RequestUserCount,
+    JoinChannel {
+        name: String,
+    },
+    LeaveChannel {
+        name: String,
+    },
+    ListChannels,
+    RequestHistory {
+        #[serde(default = "global_channel")]
+        channel: String,
+        #[serde(default)]
+        before: Option<u64>,
+        limit: usize,
+    },
+    ReloadConfig,
+}
+
+impl ServerPacket {
+    /// Returns the name of the packet, as sent in `m`.
+    fn kind(&self) -> &'static str {
+        match self {
+            ServerPacket::RequestMojangInfo => "RequestMojangInfo",
+            ServerPacket::LoginMojang(_) => "LoginMojang",
+            ServerPacket::Login { .. } => "Login",
+            ServerPacket::LoginJWT { .. } => "LoginJWT",
+            ServerPacket::LoginAnonymous => "LoginAnonymous",
+            ServerPacket::RequestJWT => "RequestJWT",
+            ServerPacket::Message { .. } => "Message",
+            ServerPacket::PrivateMessage { .. } => "PrivateMessage",
+            ServerPacket::BanUser { .. } => "BanUser",
+            ServerPacket::UnbanUser { .. } => "UnbanUser",
+            ServerPacket::BanIp { .. } => "BanIp",
+            ServerPacket::UnbanIp { .. } => "UnbanIp",
+            ServerPacket::RequestAlts { .. } => "RequestAlts",
+            ServerPacket::MuteUser { .. } => "MuteUser",
+            ServerPacket::UnmuteUser { .. } => "UnmuteUser",
+            ServerPacket::KickUser { .. } => "KickUser",
+            ServerPacket::GrantRole { .. } => "GrantRole",
+            ServerPacket::RevokeRole { .. } => "RevokeRole",
+            ServerPacket::Broadcast { .. } => "Broadcast",
+            ServerPacket::RequestAuditLog { .. } => "RequestAuditLog",
+            ServerPacket::RequestUserCount => "RequestUserCount",
+            ServerPacket::JoinChannel { .. } => "JoinChannel",
+            ServerPacket::LeaveChannel { .. } => "LeaveChannel",
+            ServerPacket::ListChannels => "ListChannels",
+            ServerPacket::RequestHistory { .. } => "RequestHistory",
+            ServerPacket::ReloadConfig => "ReloadConfig",
+        }
+    }
 }
 
 #[derive(Message)]
@@ -175,11 +581,32 @@ struct User {
     pub uuid: Uuid,
     /// Should this user allow private messages?
     pub allow_messages: bool,
+    /// Is this a guest which logged in anonymously?
+    #[serde(skip)]
+    pub anonymous: bool,
+}
+
+#[derive(Serialize, Clone)]
+struct ChannelInfo {
+    name: String,
+    description: String,
+    /// Has the requesting connection joined this channel?
+    joined: bool,
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 enum SuccessReason {
     Login,
     Ban,
     Unban,
+    BanIp,
+    UnbanIp,
+    JoinChannel,
+    LeaveChannel,
+    Mute,
+    Unmute,
+    Kick,
+    GrantRole,
+    RevokeRole,
+    Broadcast,
 }
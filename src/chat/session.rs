@@ -1,21 +1,63 @@
 use super::{
-    ChatServer, ClientPacket, Disconnect, InternalId, ServerPacket, ServerPacketId,
+    ChatServer, ClientPacket, CloseSession, Disconnect, InternalId, ServerPacket, ServerPacketId,
     connect::Connect,
 };
 
+use crate::config::NetConfig;
+use crate::limit::ConnectionPermit;
+use crate::metrics::Metrics;
 use log::*;
 
 use actix::*;
 use actix_web_actors::ws;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The maximum length of the reason in a close frame.
+const MAX_CLOSE_REASON: usize = 123;
 
 pub struct Session {
     id: InternalId,
     addr: Addr<ChatServer>,
+    metrics: Arc<Metrics>,
+    heartbeat_interval: Duration,
+    client_timeout: Duration,
+    /// The last time anything was received from the client.
+    last_seen: Instant,
+    permit: ConnectionPermit,
 }
 
 impl Session {
-    pub fn new(id: InternalId, addr: Addr<ChatServer>) -> Session {
-        Session { id, addr }
+    pub fn new(
+        id: InternalId,
+        addr: Addr<ChatServer>,
+        metrics: Arc<Metrics>,
+        net: &NetConfig,
+        permit: ConnectionPermit,
+    ) -> Session {
+        Session {
+            id,
+            addr,
+            metrics,
+            heartbeat_interval: *net.heartbeat_interval,
+            client_timeout: *net.client_timeout,
+            last_seen: Instant::now(),
+            permit,
+        }
+    }
+
+    /// Pings the client regularly and stops the session if it doesn't answer.
+    fn heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(self.heartbeat_interval, |actor, ctx| {
+            if actor.last_seen.elapsed() > actor.client_timeout {
+                info!("Connection `{}` timed out.", actor.id);
+                ctx.stop();
+            } else {
+                ctx.ping(b"");
+            }
+        });
     }
 }
 
@@ -23,13 +65,16 @@ impl Actor for Session {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+
         // Updated approach that avoids borrowing issues with ctx
         let addr = self.addr.clone();
-        let recipient = ctx.address().recipient();
+        let session = ctx.address();
+        let ip = self.permit.ip();
 
         // Use a proper async spawn that avoids borrowing ctx in the async block
         ctx.wait(
-            async move { addr.send(Connect::new(recipient)).await }
+            async move { addr.send(Connect::new(session, ip)).await }
                 .into_actor(self)
                 .map(|res, actor, _ctx| match res {
                     Ok(id) => {
@@ -61,6 +106,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
         };
 
         debug!("Received message {:?}", msg);
+        self.last_seen = Instant::now();
         match msg {
             ws::Message::Ping(msg) => ctx.pong(&msg),
             ws::Message::Pong(_msg) => {}
@@ -111,7 +157,65 @@ impl Handler<ClientPacket> for Session {
     type Result = ();
 
     fn handle(&mut self, msg: ClientPacket, ctx: &mut Self::Context) {
+        if let ClientPacket::Error { message } = &msg {
+            self.metrics.client_error(message.kind());
+        }
         let msg = serde_json::to_string(&msg).expect("could not encode message");
         ctx.text(msg);
     }
 }
+
+impl Handler<CloseSession> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSession, ctx: &mut Self::Context) {
+        info!("Closing connection `{}` with code {}.", self.id, msg.code);
+        ctx.close(Some(ws::CloseReason {
+            code: msg.code.into(),
+            description: msg.reason.map(close_description),
+        }));
+        ctx.stop();
+    }
+}
+
+/// Cuts the reason to fit into a close frame, whose payload is limited to
+/// 125 bytes including the close code.
+fn close_description(mut reason: String) -> String {
+    reason.truncate(reason.floor_char_boundary(MAX_CLOSE_REASON));
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_CLOSE_REASON, close_description};
+    use actix_http::ws::Parser;
+    use actix_web::web::BytesMut;
+    use actix_web_actors::ws;
+
+    #[test]
+    fn cuts_long_close_reasons() {
+        let short = String::from("Spamming");
+        assert_eq!(close_description(short.clone()), short);
+
+        let ascii = "a".repeat(200);
+        assert_eq!(close_description(ascii), "a".repeat(MAX_CLOSE_REASON));
+
+        // The cut must not split characters.
+        let umlauts = "ä".repeat(100);
+        assert_eq!(umlauts.len(), 200);
+        assert_eq!(close_description(umlauts), "ä".repeat(61));
+    }
+
+    #[test]
+    fn close_frames_stay_within_limit() {
+        let mut frame = BytesMut::new();
+        let reason = ws::CloseReason {
+            code: ws::CloseCode::Other(4000),
+            description: Some(close_description("x".repeat(200))),
+        };
+        Parser::write_close(&mut frame, Some(reason), false);
+        // Two header bytes, then the payload with a 7 bit length.
+        assert_eq!(frame[1] as usize, frame.len() - 2);
+        assert!(frame.len() - 2 <= 125);
+    }
+}
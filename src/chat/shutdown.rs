@@ -0,0 +1,49 @@
+use log::*;
+
+use super::{ChatServer, ClientPacket, CloseSession};
+use crate::config::WDuration;
+use actix::*;
+
+/// The WebSocket close code for a server going away.
+const GOING_AWAY: u16 = 1001;
+
+/// Notifies every client that the server shuts down, closes their connections
+/// and flushes the stored state.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub reason: String,
+    pub reconnect_after: Option<WDuration>,
+}
+
+impl Handler<Shutdown> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Shutdown, _ctx: &mut Context<Self>) {
+        info!(
+            "Closing {} connections for shutdown.",
+            self.connections.len()
+        );
+        let packet = ClientPacket::ServerShutdown {
+            reason: msg.reason.clone(),
+            reconnect_after: msg.reconnect_after.map(|duration| duration.as_secs()),
+        };
+        for session in self.connections.values() {
+            session.addr.do_send(packet.clone());
+            session.addr.do_send(CloseSession {
+                code: GOING_AWAY,
+                reason: Some(msg.reason.clone()),
+            });
+        }
+
+        if let Err(err) = self.moderation.flush() {
+            error!("Could not flush moderation storage: {}", err);
+        }
+        if let Err(err) = self.history.flush() {
+            error!("Could not flush history: {}", err);
+        }
+        if let Err(err) = self.audit_log.flush() {
+            error!("Could not flush audit log: {}", err);
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::error::*;
+use crate::ip::IpRange;
+use crate::moderation::{MODERATOR_ROLE, Permission};
 use jsonwebtoken::Algorithm;
 use serde::{
     Deserialize, Serialize,
@@ -6,6 +8,7 @@ use serde::{
     ser::Serializer,
 };
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env, fmt,
     fs::{self, File},
     io::{self, Read},
@@ -14,6 +17,7 @@ use std::{
     path::PathBuf,
     time::Duration,
 };
+use url::Url;
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -27,9 +31,22 @@ pub struct Config {
     pub moderation: ModConfig,
 
     pub auth: Option<AuthConfig>,
+
+    #[serde(default)]
+    pub mojang: MojangConfig,
+
+    #[serde(default)]
+    pub providers: ProvidersConfig,
+
+    /// The HTTP admin API at `/admin`; it is disabled if this is missing.
+    pub admin: Option<AdminConfig>,
+
+    /// The chat channels; a `global` channel is always available.
+    #[serde(default)]
+    pub channels: Vec<ChannelConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct NetConfig {
     /// The address the server will listen at.
     pub address: SocketAddr,
@@ -39,6 +56,63 @@ pub struct NetConfig {
     /// The SSL key file.
     /// If the extension is `pem`, `PEM` format will be used, otherwise `ASN1`.
     pub key_file: Option<PathBuf>,
+
+    /// Whether Prometheus metrics are served at `/metrics`.
+    #[serde(default)]
+    pub metrics: bool,
+    /// The address the metrics are served at.
+    /// If it is not set, they are served at `address`.
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Proxies whose `forwarded_header` is trusted.
+    /// Entries are IP addresses or CIDR blocks like `10.0.0.0/8`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpRange>,
+    /// The header trusted proxies send the client address in.
+    /// Only this header is read, since proxies pass other headers of clients through.
+    #[serde(default)]
+    pub forwarded_header: ForwardedHeader,
+    /// Whether every connection starts with a PROXY protocol header.
+    /// Connections are only accepted from `trusted_proxies`; TLS is not supported.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// The maximum of open connections; unlimited if not set.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// The maximum of open connections from one IP address; unlimited if not set.
+    #[serde(default)]
+    pub max_connections_per_ip: Option<usize>,
+    /// The maximum of new connections from one IP address per minute; unlimited if not set.
+    #[serde(default)]
+    pub connections_per_minute: Option<usize>,
+    /// The prefix length IPv6 addresses are grouped by for the per-IP limits,
+    /// since a single client usually has a whole /64 block.
+    #[serde(default = "NetConfig::default_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+
+    /// The interval in which the server pings every client.
+    #[serde(default = "NetConfig::default_heartbeat_interval")]
+    pub heartbeat_interval: WDuration,
+
+    /// Connections which haven't sent anything in this time are closed.
+    #[serde(default = "NetConfig::default_client_timeout")]
+    pub client_timeout: WDuration,
+
+    /// The time between failing readiness checks and closing the listener
+    /// after receiving SIGTERM or SIGINT, giving load balancers time to react.
+    #[serde(default = "NetConfig::default_shutdown_delay")]
+    pub shutdown_delay: WDuration,
+
+    /// The time clients have to disconnect after being notified of the shutdown.
+    /// Remaining connections are closed forcibly afterwards.
+    #[serde(default = "NetConfig::default_drain_timeout")]
+    pub drain_timeout: WDuration,
+
+    /// The time after which clients are told to reconnect when the server shuts down.
+    #[serde(default)]
+    pub reconnect_after: Option<WDuration>,
 }
 
 impl Default for NetConfig {
@@ -47,11 +121,48 @@ impl Default for NetConfig {
             address: ([127, 0, 0, 1], 8080).into(),
             cert_file: None,
             key_file: None,
+            metrics: false,
+            metrics_address: None,
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+            proxy_protocol: false,
+            max_connections: None,
+            max_connections_per_ip: None,
+            connections_per_minute: None,
+            ipv6_prefix: NetConfig::default_ipv6_prefix(),
+            heartbeat_interval: NetConfig::default_heartbeat_interval(),
+            client_timeout: NetConfig::default_client_timeout(),
+            shutdown_delay: NetConfig::default_shutdown_delay(),
+            drain_timeout: NetConfig::default_drain_timeout(),
+            reconnect_after: None,
         }
     }
 }
 
+impl NetConfig {
+    fn default_ipv6_prefix() -> u8 {
+        64
+    }
+
+    fn default_heartbeat_interval() -> WDuration {
+        Duration::from_secs(10).into()
+    }
+
+    fn default_client_timeout() -> WDuration {
+        Duration::from_secs(30).into()
+    }
+
+    fn default_shutdown_delay() -> WDuration {
+        Duration::from_secs(0).into()
+    }
+
+    fn default_drain_timeout() -> WDuration {
+        Duration::from_secs(10).into()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct MsgConfig {
     /// The maximum message length in chars.
     pub max_length: usize,
@@ -61,6 +172,17 @@ pub struct MsgConfig {
 
     /// The duration in which the amount of messages cannot be greater.
     pub count_duration: WDuration,
+
+    /// The amount of messages kept in memory for each channel.
+    pub history_size: usize,
+
+    /// The file every message is appended to (JSON lines).
+    /// If set, the history is restored from it at startup.
+    /// Once it has grown large, only the last `history_size` messages of each channel are kept.
+    pub history_file: Option<PathBuf>,
+
+    /// The amount of messages of each joined channel sent after login.
+    pub history_on_login: usize,
 }
 
 impl Default for MsgConfig {
@@ -69,14 +191,60 @@ impl Default for MsgConfig {
             max_length: 100,
             max_messages: 40,
             count_duration: Duration::from_secs(60).into(),
+            history_size: 100,
+            history_file: None,
+            history_on_login: 0,
+        }
+    }
+}
+
+impl MsgConfig {
+    /// The default limits for anonymous users.
+    fn anonymous() -> MsgConfig {
+        MsgConfig {
+            max_messages: 10,
+            ..MsgConfig::default()
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChannelConfig {
+    /// The name clients use to refer to this channel.
+    pub name: String,
+
+    /// A short description shown to clients listing the channels.
+    #[serde(default)]
+    pub description: String,
+
+    /// Whether new connections join this channel automatically.
+    #[serde(default)]
+    pub default_joined: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AuthConfig {
-    /// The file containing the key of the JWT
-    pub key_file: PathBuf,
+    /// The file containing the secret for HMAC algorithms,
+    /// or the private key for the others.
+    /// Without a private key, tokens can only be verified.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+
+    /// The file containing the public key for RSA, ECDSA and EdDSA algorithms.
+    /// It is derived from the private key if it is missing.
+    /// Keys are read as PEM if the extension is `pem`, otherwise as DER.
+    #[serde(default)]
+    pub public_key_file: Option<PathBuf>,
+
+    /// The keys tokens are signed and verified with, identified by their `kid`.
+    /// Used instead of `key_file` and `public_key_file` to rotate keys.
+    #[serde(default)]
+    pub keys: Vec<JwtKeyConfig>,
+
+    /// The `kid` of the key new tokens are signed with.
+    /// Without it, tokens can only be verified.
+    #[serde(default)]
+    pub active_key: Option<String>,
 
     /// The JWT algorithm
     pub algorithm: Algorithm,
@@ -86,36 +254,512 @@ pub struct AuthConfig {
 
     /// Whether users can be anonymous
     pub allow_anonymous: bool,
+
+    /// The message limits anonymous users are subject to.
+    #[serde(default = "MsgConfig::anonymous")]
+    pub anonymous_message: MsgConfig,
+}
+
+impl AuthConfig {
+    fn validate(&self) -> Result<()> {
+        if self.keys.is_empty() {
+            if self.active_key.is_some() {
+                return Err(invalid_config("`auth.active_key` requires `auth.keys`"));
+            }
+            return Ok(());
+        }
+        if self.key_file.is_some() || self.public_key_file.is_some() {
+            return Err(invalid_config(
+                "`auth.key_file` and `auth.public_key_file` can't be combined with `auth.keys`",
+            ));
+        }
+
+        let mut kids = BTreeSet::new();
+        for key in &self.keys {
+            if key.kid.is_empty() {
+                return Err(invalid_config("key ids must not be empty"));
+            }
+            if !kids.insert(&key.kid) {
+                return Err(invalid_config(format!(
+                    "key id `{}` is used multiple times",
+                    key.kid
+                )));
+            }
+        }
+        if let Some(active) = &self.active_key
+            && !kids.contains(active)
+        {
+            return Err(invalid_config(format!(
+                "`auth.active_key` refers to unknown key `{}`",
+                active
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JwtKeyConfig {
+    /// The identifier in the header of tokens signed with this key.
+    pub kid: String,
+
+    /// The algorithm of this key, if it differs from `auth.algorithm`.
+    #[serde(default)]
+    pub algorithm: Option<Algorithm>,
+
+    /// The secret or private key, as in `auth.key_file`.
+    /// Keys which are being retired only need their public key.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+
+    /// The public key, as in `auth.public_key_file`.
+    #[serde(default)]
+    pub public_key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MojangConfig {
+    /// The base URL of the Yggdrasil-compatible session server logins are verified with.
+    pub session_server: String,
+
+    /// The time a request to the session server may take.
+    pub timeout: WDuration,
+
+    /// How often a request is repeated if the session server can't be reached
+    /// or answers with a server error.
+    pub retries: u32,
+
+    /// The time before the first retry; it doubles with every further retry.
+    pub retry_backoff: WDuration,
+
+    /// The number of failed logins in a row after which the session server
+    /// is considered unavailable. If it is 0, it is always contacted.
+    pub failure_threshold: u32,
+
+    /// The time logins are rejected for once the session server is considered unavailable.
+    pub unavailable_time: WDuration,
+}
+
+impl Default for MojangConfig {
+    fn default() -> MojangConfig {
+        MojangConfig {
+            session_server: String::from("https://sessionserver.mojang.com"),
+            timeout: Duration::from_secs(5).into(),
+            retries: 2,
+            retry_backoff: Duration::from_millis(250).into(),
+            failure_threshold: 5,
+            unavailable_time: Duration::from_secs(30).into(),
+        }
+    }
+}
+
+impl MojangConfig {
+    /// Returns the URL of the `hasJoined` endpoint of the session server.
+    pub fn has_joined_url(&self) -> std::result::Result<Url, url::ParseError> {
+        // Without a trailing slash, the last path segment would be replaced.
+        let mut base = Url::parse(&self.session_server)?;
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        base.join("session/minecraft/hasJoined")
+    }
+
+    /// Returns these settings for another session server.
+    pub fn with_session_server(&self, session_server: &str) -> MojangConfig {
+        MojangConfig {
+            session_server: session_server.to_string(),
+            ..self.clone()
+        }
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        match self.has_joined_url() {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            Ok(_) => Err(invalid_config(format!(
+                "`{}` must be an HTTP or HTTPS URL",
+                name
+            ))),
+            Err(err) => Err(invalid_config(format!("invalid `{}`: {}", name, err))),
+        }
+    }
+}
+
+/// The providers clients can log in with using the `Login` packet,
+/// in addition to `mojang` and `jwt`, which is available if `auth` is configured.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ProvidersConfig {
+    /// Whether clients can log in with any name and uuid without proof, using `offline`.
+    /// This is only meant for development and tests.
+    pub offline: bool,
+
+    /// Providers verifying logins with lists of Yggdrasil-compatible session servers,
+    /// which are asked in order. They use the `mojang` settings apart from `session_server`.
+    pub yggdrasil: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, as sent by nginx with `$proxy_add_x_forwarded_for`.
+    #[default]
+    XForwardedFor,
+    /// `Forwarded` as in RFC 7239.
+    Forwarded,
+    /// No header is read; the address is only taken from the PROXY protocol.
+    None,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AdminConfig {
+    /// The file containing the bearer token clients of the admin API have to send.
+    pub token_file: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Plain files configured in `moderators`, `banned`, `muted`, `ip_banned`,
+    /// `role_assignments`, `users` and `addresses`.
+    File,
+    /// An SQLite database at `database`.
+    #[cfg(feature = "sqlite")]
+    Sqlite,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct ModConfig {
+    /// Where bans, mutes, role assignments and users are stored.
+    pub storage: StorageBackend,
+
+    /// The SQLite database used by the `sqlite` storage backend.
+    pub database: PathBuf,
+
     /// The file containing the moderators (line separated).
+    /// The SQLite backend imports it once when it creates the database.
+    /// Users listed in it have the `moderator` role.
     pub moderators: PathBuf,
 
-    /// The file containing the banned users (line separated).
+    /// The file containing the roles assigned to users
+    /// (JSON object mapping uuids to lists of role names).
+    pub role_assignments: PathBuf,
+
+    /// The file containing the users who have logged in (JSON lines).
+    pub users: PathBuf,
+
+    /// The file containing the IP addresses users have logged in from (JSON lines).
+    pub addresses: PathBuf,
+
+    /// How long the addresses of users are kept after their last login from them.
+    /// If it is `0s`, no addresses are recorded.
+    pub address_retention: WDuration,
+
+    /// The file moderation actions are logged to (JSON lines).
+    pub audit_log: PathBuf,
+
+    /// The amount of the newest audit log entries kept in memory.
+    /// `RequestAuditLog` packets are answered from them and can't request more.
+    pub audit_log_tail: usize,
+
+    /// The roles and the permissions they grant.
+    pub roles: BTreeMap<String, BTreeSet<Permission>>,
+
+    /// The file containing the bans (JSON lines).
+    /// Lines only containing a uuid are read as permanent bans.
     pub banned: PathBuf,
+
+    /// The file containing the mutes (JSON lines).
+    pub muted: PathBuf,
+
+    /// The file containing the IP bans (JSON lines).
+    /// Lines only containing an address or a CIDR block are read as permanent bans.
+    pub ip_banned: PathBuf,
+
+    /// The interval in which expired bans and mutes are lifted.
+    pub expiry_check_interval: WDuration,
+
+    /// The interval in which the moderation files are checked for changes made by hand.
+    /// If it is not set, they are only reloaded on SIGHUP.
+    pub reload_interval: Option<WDuration>,
+
+    /// The WebSocket close code sent to connections of newly banned users.
+    /// If it is not set, banned users stay connected.
+    /// Close codes have to be between 1000 and 4999, except for the reserved ones.
+    pub ban_close_code: Option<u16>,
+
+    /// The WebSocket close code sent to connections of kicked users.
+    pub kick_close_code: u16,
 }
 
 impl Default for ModConfig {
     fn default() -> ModConfig {
         ModConfig {
+            storage: StorageBackend::File,
+            database: PathBuf::from("./axochat.sqlite"),
             moderators: PathBuf::from("./moderators.txt"),
+            role_assignments: PathBuf::from("./roles.json"),
+            users: PathBuf::from("./users.jsonl"),
+            addresses: PathBuf::from("./addresses.jsonl"),
+            address_retention: Duration::from_secs(30 * 24 * 60 * 60).into(),
+            audit_log: PathBuf::from("./audit.jsonl"),
+            audit_log_tail: 1000,
+            roles: BTreeMap::from([
+                (
+                    String::from("admin"),
+                    Permission::ALL.iter().copied().collect(),
+                ),
+                (
+                    String::from(MODERATOR_ROLE),
+                    BTreeSet::from([
+                        Permission::Ban,
+                        Permission::Mute,
+                        Permission::Kick,
+                        Permission::ViewUserCount,
+                        Permission::ViewAuditLog,
+                        Permission::ViewAlts,
+                    ]),
+                ),
+                (
+                    String::from("helper"),
+                    BTreeSet::from([Permission::Mute, Permission::Kick]),
+                ),
+                (String::from("vip"), BTreeSet::new()),
+            ]),
             banned: PathBuf::from("./banned.txt"),
+            muted: PathBuf::from("./muted.txt"),
+            ip_banned: PathBuf::from("./ip_banned.txt"),
+            expiry_check_interval: Duration::from_secs(30).into(),
+            reload_interval: Some(Duration::from_secs(10).into()),
+            ban_close_code: None,
+            kick_close_code: 1008,
+        }
+    }
+}
+
+impl Config {
+    /// Checks the settings which can't be checked while deserializing.
+    pub fn validate(&self) -> Result<()> {
+        let mut names = BTreeSet::new();
+        for channel in &self.channels {
+            if channel.name.is_empty() {
+                return Err(invalid_config("channel names must not be empty"));
+            }
+            if !names.insert(&channel.name) {
+                return Err(invalid_config(format!(
+                    "channel `{}` is declared twice",
+                    channel.name
+                )));
+            }
         }
+
+        if self.net.proxy_protocol {
+            if self.net.trusted_proxies.is_empty() {
+                return Err(invalid_config(
+                    "`net.proxy_protocol` requires `net.trusted_proxies`",
+                ));
+            }
+            if self.net.cert_file.is_some() {
+                return Err(invalid_config(
+                    "`net.proxy_protocol` can't be used with TLS",
+                ));
+            }
+        }
+
+        if !(1..=128).contains(&self.net.ipv6_prefix) {
+            return Err(invalid_config(
+                "`net.ipv6_prefix` must be between 1 and 128",
+            ));
+        }
+        if self.net.heartbeat_interval.is_zero() {
+            return Err(invalid_config("`net.heartbeat_interval` must not be 0"));
+        }
+        if *self.net.client_timeout <= *self.net.heartbeat_interval {
+            return Err(invalid_config(
+                "`net.client_timeout` must be longer than `net.heartbeat_interval`",
+            ));
+        }
+
+        if self.moderation.expiry_check_interval.is_zero() {
+            return Err(invalid_config(
+                "`moderation.expiry_check_interval` must not be 0",
+            ));
+        }
+        if self
+            .moderation
+            .reload_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(invalid_config(
+                "`moderation.reload_interval` must not be 0, leave it out to only reload on SIGHUP",
+            ));
+        }
+        if let Some(code) = self.moderation.ban_close_code {
+            validate_close_code("moderation.ban_close_code", code)?;
+        }
+        validate_close_code(
+            "moderation.kick_close_code",
+            self.moderation.kick_close_code,
+        )?;
+
+        self.mojang.validate("mojang.session_server")?;
+        if self.mojang.timeout.is_zero() {
+            return Err(invalid_config("`mojang.timeout` must not be 0"));
+        }
+        for (name, urls) in &self.providers.yggdrasil {
+            if matches!(name.as_str(), "mojang" | "jwt" | "offline") {
+                return Err(invalid_config(format!(
+                    "provider name `{}` is reserved",
+                    name
+                )));
+            }
+            if urls.is_empty() {
+                return Err(invalid_config(format!(
+                    "provider `{}` needs at least one session server",
+                    name
+                )));
+            }
+            for url in urls {
+                self.mojang
+                    .with_session_server(url)
+                    .validate(&format!("providers.yggdrasil.{}", name))?;
+            }
+        }
+
+        if self.message.max_length == 0 {
+            return Err(invalid_config("`message.max_length` must not be 0"));
+        }
+        if let Some(auth) = &self.auth {
+            if auth.anonymous_message.max_length == 0 {
+                return Err(invalid_config(
+                    "`auth.anonymous_message.max_length` must not be 0",
+                ));
+            }
+            auth.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes over every setting of `new` which can be changed while the server is running.
+    /// Returns the names of the changed settings which only take effect after a restart.
+    pub fn update(&mut self, mut new: Config) -> Vec<&'static str> {
+        let mut restart_required = Vec::new();
+        let mut keep = |name, changed: bool| {
+            if changed {
+                restart_required.push(name);
+            }
+        };
+
+        keep("net", self.net != new.net);
+        new.net = self.net.clone();
+
+        keep("admin", self.admin != new.admin);
+        new.admin = self.admin.clone();
+
+        let (old_msg, new_msg) = (&self.message, &mut new.message);
+        keep(
+            "message.history_size",
+            old_msg.history_size != new_msg.history_size,
+        );
+        keep(
+            "message.history_file",
+            old_msg.history_file != new_msg.history_file,
+        );
+        new_msg.history_size = old_msg.history_size;
+        new_msg.history_file = old_msg.history_file.clone();
+
+        let (old_mod, new_mod) = (&self.moderation, &mut new.moderation);
+        keep("moderation.storage", old_mod.storage != new_mod.storage);
+        keep("moderation.database", old_mod.database != new_mod.database);
+        keep(
+            "moderation.role_assignments",
+            old_mod.role_assignments != new_mod.role_assignments,
+        );
+        keep("moderation.users", old_mod.users != new_mod.users);
+        keep(
+            "moderation.addresses",
+            old_mod.addresses != new_mod.addresses,
+        );
+        keep(
+            "moderation.audit_log",
+            old_mod.audit_log != new_mod.audit_log,
+        );
+        keep(
+            "moderation.audit_log_tail",
+            old_mod.audit_log_tail != new_mod.audit_log_tail,
+        );
+        keep("moderation.banned", old_mod.banned != new_mod.banned);
+        keep("moderation.muted", old_mod.muted != new_mod.muted);
+        keep(
+            "moderation.ip_banned",
+            old_mod.ip_banned != new_mod.ip_banned,
+        );
+        keep(
+            "moderation.expiry_check_interval",
+            old_mod.expiry_check_interval != new_mod.expiry_check_interval,
+        );
+        keep(
+            "moderation.reload_interval",
+            old_mod.reload_interval != new_mod.reload_interval,
+        );
+        new_mod.storage = old_mod.storage;
+        new_mod.database = old_mod.database.clone();
+        new_mod.role_assignments = old_mod.role_assignments.clone();
+        new_mod.users = old_mod.users.clone();
+        new_mod.addresses = old_mod.addresses.clone();
+        new_mod.audit_log = old_mod.audit_log.clone();
+        new_mod.audit_log_tail = old_mod.audit_log_tail;
+        new_mod.banned = old_mod.banned.clone();
+        new_mod.muted = old_mod.muted.clone();
+        new_mod.ip_banned = old_mod.ip_banned.clone();
+        new_mod.expiry_check_interval = old_mod.expiry_check_interval;
+        new_mod.reload_interval = old_mod.reload_interval;
+
+        *self = new;
+        restart_required
+    }
+}
+
+/// Checks that the close code may be sent by servers (RFC 6455, section 7.4).
+fn validate_close_code(name: &str, code: u16) -> Result<()> {
+    match code {
+        1004..=1006 | 1015 => Err(invalid_config(format!(
+            "`{}` is a reserved close code",
+            name
+        ))),
+        1000..=4999 => Ok(()),
+        _ => Err(invalid_config(format!(
+            "`{}` must be between 1000 and 4999",
+            name
+        ))),
+    }
+}
+
+fn invalid_config(message: impl Into<String>) -> Error {
+    Error::InvalidConfig {
+        message: message.into(),
     }
 }
 
+fn config_path() -> PathBuf {
+    let path = env::var("CONFIG_PATH").unwrap_or_else(|_| String::from("./axochat.toml"));
+    PathBuf::from(path)
+}
+
 /// Reads the configuration file at `$CONFIG_PATH` or creates one if none was found.
 pub fn read_config() -> Result<Config> {
-    let path = env::var("CONFIG_PATH").unwrap_or_else(|_| String::from("./axochat.toml"));
-    let path = PathBuf::from(path);
+    let path = config_path();
 
     match File::open(&path) {
         Ok(mut file) => {
             let mut input = String::new();
             file.read_to_string(&mut input)?;
-            Ok(toml::from_str(&input)?)
+            let cfg: Config = toml::from_str(&input)?;
+            cfg.validate()?;
+            Ok(cfg)
         }
         Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
             let cfg = Config::default();
@@ -127,6 +771,14 @@ pub fn read_config() -> Result<Config> {
     }
 }
 
+/// Reads the configuration file at `$CONFIG_PATH` again.
+/// Unlike [`read_config`], this fails if the file is missing.
+pub fn reload_config() -> Result<Config> {
+    let cfg: Config = toml::from_str(&fs::read_to_string(config_path())?)?;
+    cfg.validate()?;
+    Ok(cfg)
+}
+
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct WDuration(Duration);
 
@@ -1,11 +1,12 @@
 use log::SetLoggerError;
 use serde::Serialize;
 use snafu::Snafu;
-use std::{error, fmt, io};
+use std::{error, fmt, io, path::PathBuf};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 // Removed the From derive to avoid conflicts with manually implemented From traits
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("I/O: {}", source))]
@@ -25,6 +26,9 @@ pub enum Error {
     #[cfg(feature = "rustls-tls")]
     #[snafu(display("rustls"))]
     RustTLSNoMsg,
+    #[cfg(feature = "sqlite")]
+    #[snafu(display("SQLite: {}", source))]
+    SQLite { source: rusqlite::Error },
     #[snafu(display("JWT: {}", source))]
     JWT { source: jsonwebtoken::errors::Error },
     #[snafu(display("UUID parsing: {}", source))]
@@ -33,6 +37,18 @@ pub enum Error {
     AxoChat { source: ClientError },
     #[snafu(display("Logger: {}", source))]
     Logger { source: SetLoggerError },
+    #[snafu(display("invalid configuration: {}", message))]
+    InvalidConfig { message: String },
+    #[snafu(display("{:?}: {}", path, message))]
+    InvalidKey { path: PathBuf, message: String },
+    #[snafu(display("{:?} is locked by another process", path))]
+    Locked { path: PathBuf },
+    #[snafu(display("{:?}, line {}: {}", path, line, message))]
+    Malformed {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
 }
 
 // Manually implement From traits to avoid conflicts
@@ -67,6 +83,13 @@ impl From<openssl::error::ErrorStack> for Error {
     }
 }
 
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(source: rusqlite::Error) -> Self {
+        Error::SQLite { source }
+    }
+}
+
 impl From<jsonwebtoken::errors::Error> for Error {
     fn from(source: jsonwebtoken::errors::Error) -> Self {
         Error::JWT { source }
@@ -90,21 +113,72 @@ impl From<ClientError> for Error {
 pub enum ClientError {
     NotSupported,
     LoginFailed,
+    AuthUnavailable,
     NotLoggedIn,
     AlreadyLoggedIn,
     MojangRequestMissing,
     NotPermitted,
     NotBanned,
-    Banned,
+    Banned {
+        reason: Option<String>,
+        /// The unix timestamp in seconds at which the ban ends.
+        until: Option<u64>,
+    },
+    NotMuted,
+    Muted {
+        reason: Option<String>,
+        /// The unix timestamp in seconds at which the mute ends.
+        until: Option<u64>,
+    },
+    NotConnected,
+    UnknownRole,
+    RoleNotAssigned,
     RateLimited,
     PrivateMessageNotAccepted,
     EmptyMessage,
     MessageTooLong,
     InvalidCharacter(char),
     InvalidId,
+    UnknownChannel,
+    NotInChannel,
+    InvalidConfig(String),
     Internal,
 }
 
+impl ClientError {
+    /// Returns the name of the variant, e.g. for metrics.
+    pub fn kind(&self) -> &'static str {
+        use self::ClientError::*;
+
+        match self {
+            NotSupported => "NotSupported",
+            LoginFailed => "LoginFailed",
+            AuthUnavailable => "AuthUnavailable",
+            NotLoggedIn => "NotLoggedIn",
+            AlreadyLoggedIn => "AlreadyLoggedIn",
+            MojangRequestMissing => "MojangRequestMissing",
+            NotPermitted => "NotPermitted",
+            NotBanned => "NotBanned",
+            Banned { .. } => "Banned",
+            NotMuted => "NotMuted",
+            Muted { .. } => "Muted",
+            NotConnected => "NotConnected",
+            UnknownRole => "UnknownRole",
+            RoleNotAssigned => "RoleNotAssigned",
+            RateLimited => "RateLimited",
+            PrivateMessageNotAccepted => "PrivateMessageNotAccepted",
+            EmptyMessage => "EmptyMessage",
+            MessageTooLong => "MessageTooLong",
+            InvalidCharacter(_) => "InvalidCharacter",
+            InvalidId => "InvalidId",
+            UnknownChannel => "UnknownChannel",
+            NotInChannel => "NotInChannel",
+            InvalidConfig(_) => "InvalidConfig",
+            Internal => "Internal",
+        }
+    }
+}
+
 impl error::Error for ClientError {}
 
 impl fmt::Display for ClientError {
@@ -114,12 +188,36 @@ impl fmt::Display for ClientError {
         match self {
             NotSupported => write!(f, "method not supported"),
             LoginFailed => write!(f, "login failed"),
+            AuthUnavailable => write!(f, "authentication server unavailable"),
             NotLoggedIn => write!(f, "not logged in"),
             AlreadyLoggedIn => write!(f, "already logged in"),
             MojangRequestMissing => write!(f, "mojang request missing"),
             NotPermitted => write!(f, "not permitted"),
             NotBanned => write!(f, "not banned"),
-            Banned => write!(f, "banned"),
+            Banned { reason, until } => {
+                write!(f, "banned")?;
+                if let Some(reason) = reason {
+                    write!(f, " for `{}`", reason)?;
+                }
+                if let Some(until) = until {
+                    write!(f, " until {}", until)?;
+                }
+                Ok(())
+            }
+            NotMuted => write!(f, "not muted"),
+            Muted { reason, until } => {
+                write!(f, "muted")?;
+                if let Some(reason) = reason {
+                    write!(f, " for `{}`", reason)?;
+                }
+                if let Some(until) = until {
+                    write!(f, " until {}", until)?;
+                }
+                Ok(())
+            }
+            NotConnected => write!(f, "user is not connected"),
+            UnknownRole => write!(f, "unknown role"),
+            RoleNotAssigned => write!(f, "role not assigned"),
             RateLimited => write!(f, "rate limited"),
             PrivateMessageNotAccepted => write!(f, "private message not accepted"),
             EmptyMessage => write!(f, "empty message"),
@@ -130,6 +228,9 @@ impl fmt::Display for ClientError {
                 ch.escape_default()
             ),
             InvalidId => write!(f, "invalid id"),
+            UnknownChannel => write!(f, "unknown channel"),
+            NotInChannel => write!(f, "not in channel"),
+            InvalidConfig(message) => write!(f, "invalid configuration: {}", message),
             Internal => write!(f, "internal error"),
         }
     }
@@ -0,0 +1,75 @@
+use crate::chat::{
+    ChatServer,
+    health::{CheckReadiness, Ping},
+};
+use log::*;
+
+use actix::*;
+use actix_web::{HttpResponse, web};
+use serde_json::json;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// The time the `ChatServer` has to answer health checks.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The state of the server shared with the health checks.
+#[derive(Default)]
+pub struct Health {
+    shutting_down: AtomicBool,
+}
+
+impl Health {
+    /// Makes every following readiness check fail.
+    pub fn start_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz));
+}
+
+/// Succeeds if the `ChatServer` processes messages.
+async fn healthz(srv: web::Data<Addr<ChatServer>>) -> HttpResponse {
+    match srv.send(Ping).timeout(TIMEOUT).await {
+        Ok(()) => HttpResponse::Ok().body("ok"),
+        Err(err) => {
+            warn!("Health check failed: {}", err);
+            HttpResponse::ServiceUnavailable().body("chat server unresponsive")
+        }
+    }
+}
+
+/// Succeeds if the server can serve clients and is not shutting down.
+async fn readyz(srv: web::Data<Addr<ChatServer>>, health: web::Data<Health>) -> HttpResponse {
+    let shutting_down = health.is_shutting_down();
+    match srv.send(CheckReadiness).timeout(TIMEOUT).await {
+        Ok(readiness) => {
+            let mut response = if readiness.is_ready() && !shutting_down {
+                HttpResponse::Ok()
+            } else {
+                HttpResponse::ServiceUnavailable()
+            };
+            response.json(json!({
+                "chat_server": true,
+                "moderation": readiness.moderation,
+                "shutting_down": shutting_down,
+            }))
+        }
+        Err(err) => {
+            warn!("Readiness check failed: {}", err);
+            HttpResponse::ServiceUnavailable().json(json!({
+                "chat_server": false,
+                "shutting_down": shutting_down,
+            }))
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::auth::UserInfo;
+use crate::config::MsgConfig;
+use crate::error::*;
+use crate::storage::atomic;
+use crate::time::unix_time;
+use log::*;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// The history file is only compacted once it has at least this many lines.
+const MIN_COMPACT_LINES: usize = 1000;
+
+/// A message which was sent to a channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub channel: String,
+    pub author_info: UserInfo,
+    pub content: String,
+    /// The unix timestamp in seconds.
+    pub timestamp: u64,
+}
+
+/// Keeps the last messages of every channel in memory
+/// and optionally appends every message to a log file.
+/// Once the file has grown to twice the messages kept in memory,
+/// it is rewritten with only those.
+pub struct History {
+    channels: HashMap<String, VecDeque<HistoryEntry>>,
+    capacity: usize,
+    path: Option<PathBuf>,
+    log: Option<File>,
+    /// The amount of lines in the log file.
+    lines: usize,
+    next_id: u64,
+}
+
+impl History {
+    pub fn new(cfg: &MsgConfig) -> Result<History> {
+        let mut history = History {
+            channels: HashMap::new(),
+            capacity: cfg.history_size,
+            path: cfg.history_file.clone(),
+            log: None,
+            lines: 0,
+            next_id: 0,
+        };
+
+        if let Some(path) = &cfg.history_file {
+            atomic::recover(path)?;
+            history.load(path)?;
+            if history.lines > history.retained() {
+                history.compact(path)?;
+            } else {
+                history.log = Some(OpenOptions::new().append(true).create(true).open(path)?);
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Returns the amount of messages kept in memory.
+    fn retained(&self) -> usize {
+        self.channels.values().map(VecDeque::len).sum()
+    }
+
+    /// Rewrites the history file with only the messages kept in memory.
+    fn compact(&mut self, path: &Path) -> Result<()> {
+        let mut entries: Vec<&HistoryEntry> = self.channels.values().flatten().collect();
+        entries.sort_by_key(|entry| entry.id);
+
+        let mut buf = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut buf, entry)?;
+            buf.push(b'\n');
+        }
+        atomic::write(path, &buf)?;
+        debug!(
+            "Compacted {:?} from {} to {} messages.",
+            path,
+            self.lines,
+            entries.len()
+        );
+
+        self.lines = entries.len();
+        self.log = Some(OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> Result<()> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            self.lines += 1;
+            match serde_json::from_str::<HistoryEntry>(&line) {
+                Ok(entry) => {
+                    self.next_id = self.next_id.max(entry.id + 1);
+                    self.insert(entry);
+                }
+                Err(err) => warn!(
+                    "Skipping malformed line {} of {:?}: {}",
+                    index + 1,
+                    path,
+                    err
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let entries = self.channels.entry(entry.channel.clone()).or_default();
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Records a new message and returns its entry.
+    pub fn push(
+        &mut self,
+        channel: String,
+        author_info: UserInfo,
+        content: String,
+    ) -> HistoryEntry {
+        let entry = HistoryEntry {
+            id: self.next_id,
+            channel,
+            author_info,
+            content,
+            timestamp: unix_time(),
+        };
+        self.next_id += 1;
+
+        if let Some(log) = &mut self.log {
+            let res = serde_json::to_string(&entry)
+                .map_err(Error::from)
+                .and_then(|line| writeln!(log, "{}", line).map_err(Error::from));
+            match res {
+                Ok(()) => self.lines += 1,
+                Err(err) => warn!("Could not append message to history file: {}", err),
+            }
+        }
+
+        self.insert(entry.clone());
+        if let Some(path) = self.path.clone()
+            && self.lines >= (2 * self.retained()).max(MIN_COMPACT_LINES)
+            && let Err(err) = self.compact(&path)
+        {
+            warn!("Could not compact history file: {}", err);
+        }
+        entry
+    }
+
+    /// Makes sure every message appended to the history file is on disk.
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            log.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Returns at most `limit` of the newest messages in `channel`
+    /// with an id lower than `before`, oldest first.
+    pub fn query(&self, channel: &str, before: Option<u64>, limit: usize) -> Vec<HistoryEntry> {
+        let Some(entries) = self.channels.get(channel) else {
+            return Vec::new();
+        };
+
+        let end = match before {
+            Some(before) => entries.partition_point(|entry| entry.id < before),
+            None => entries.len(),
+        };
+        let start = end.saturating_sub(limit);
+        entries.range(start..end).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config(path: &Path) -> MsgConfig {
+        MsgConfig {
+            history_size: 10,
+            history_file: Some(path.to_path_buf()),
+            ..MsgConfig::default()
+        }
+    }
+
+    fn push(history: &mut History, channel: &str, content: String) {
+        let author_info = UserInfo {
+            name: "Notch".to_string(),
+            uuid: Uuid::nil(),
+        };
+        history.push(channel.to_string(), author_info, content);
+    }
+
+    fn line_count(path: &Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn compacts_the_history_file() {
+        let dir = std::env::temp_dir().join(format!("axochat-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::new(&config(&path)).unwrap();
+        for index in 0..MIN_COMPACT_LINES - 1 {
+            push(&mut history, "global", index.to_string());
+        }
+        push(&mut history, "other", "other".to_string());
+        assert_eq!(line_count(&path), 11);
+
+        for index in 0..15 {
+            push(&mut history, "global", index.to_string());
+        }
+        drop(history);
+        assert_eq!(line_count(&path), 26);
+
+        // Restoring the history compacts the file as well.
+        let history = History::new(&config(&path)).unwrap();
+        assert_eq!(line_count(&path), 11);
+        let messages = history.query("global", None, 100);
+        assert_eq!(messages.len(), 10);
+        assert_eq!(messages[9].content, "14");
+        assert_eq!(history.query("other", None, 100).len(), 1);
+        assert_eq!(history.next_id, MIN_COMPACT_LINES as u64 + 15);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// A single IP address or a CIDR block like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Returns whether `ip` is part of this range.
+    /// IPv4-mapped IPv6 addresses are treated like IPv4 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether the range is broader than a /8 IPv4 or a /32 IPv6 block,
+    /// so banning it would likely hit many unrelated users.
+    pub fn is_broad(&self) -> bool {
+        let min_prefix = match self.addr {
+            IpAddr::V4(_) => 8,
+            IpAddr::V6(_) => 32,
+        };
+        self.prefix < min_prefix
+    }
+}
+
+fn max_prefix(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpRange, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|err| format!("invalid address `{}`: {}", s, err))?
+            .to_canonical();
+        let max = max_prefix(addr);
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in `{}`", s))?,
+            None => max,
+        };
+
+        // Store the network address, so equal ranges compare equal.
+        let addr = match addr {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+            }
+            IpAddr::V6(addr) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+            }
+        };
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.prefix == max_prefix(self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl Serialize for IpRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<IpRange, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(range("192.0.2.1").to_string(), "192.0.2.1");
+        assert_eq!(range("192.0.2.1/32").to_string(), "192.0.2.1");
+        assert_eq!(range("192.0.2.77/24").to_string(), "192.0.2.0/24");
+        assert_eq!(range("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert_eq!(range("203.0.113.9/0"), range("0.0.0.0/0"));
+        assert_eq!(range("2001:db8::1/128").to_string(), "2001:db8::1");
+        assert_eq!(range("2001:db8:ffff::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(range("::/0").to_string(), "::/0");
+        // IPv4-mapped addresses are stored as IPv4 addresses.
+        assert_eq!(range("::ffff:192.0.2.1"), range("192.0.2.1"));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for s in [
+            "",
+            "/8",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "10.0.0.0/ 8",
+            "10.0.0.0/256",
+            "10.0.0/8",
+            "2001:db8::/129",
+            "[2001:db8::]/64",
+            "example.com",
+        ] {
+            assert!(s.parse::<IpRange>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn contains_addresses() {
+        let everything = range("0.0.0.0/0");
+        assert!(everything.contains(ip("0.0.0.0")));
+        assert!(everything.contains(ip("255.255.255.255")));
+        assert!(!everything.contains(ip("2001:db8::1")));
+
+        let single = range("192.0.2.1/32");
+        assert!(single.contains(ip("192.0.2.1")));
+        assert!(!single.contains(ip("192.0.2.2")));
+
+        let block = range("10.0.0.0/8");
+        assert!(block.contains(ip("10.255.255.255")));
+        assert!(!block.contains(ip("11.0.0.0")));
+        assert!(!block.contains(ip("9.255.255.255")));
+
+        let everything6 = range("::/0");
+        assert!(everything6.contains(ip("2001:db8::1")));
+        assert!(everything6.contains(ip("::")));
+        assert!(!everything6.contains(ip("192.0.2.1")));
+
+        let single6 = range("2001:db8::1/128");
+        assert!(single6.contains(ip("2001:db8::1")));
+        assert!(!single6.contains(ip("2001:db8::2")));
+
+        let block6 = range("2001:db8::/32");
+        assert!(block6.contains(ip("2001:db8:ffff:ffff::1")));
+        assert!(!block6.contains(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn treats_mapped_addresses_as_ipv4() {
+        assert!(range("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(range("192.0.2.1").contains(ip("::ffff:192.0.2.1")));
+        assert!(!range("192.0.2.1").contains(ip("::ffff:192.0.2.2")));
+        assert!(range("0.0.0.0/0").contains(ip("::ffff:203.0.113.9")));
+        assert!(!range("::/0").contains(ip("::ffff:203.0.113.9")));
+        // Compatible addresses aren't mapped ones.
+        assert!(!range("10.0.0.0/8").contains(ip("::10.1.2.3")));
+    }
+
+    #[test]
+    fn round_trips_through_serde() {
+        let range = range("10.0.0.0/8");
+        let json = serde_json::to_string(&range).unwrap();
+        assert_eq!(json, r#""10.0.0.0/8""#);
+        assert_eq!(serde_json::from_str::<IpRange>(&json).unwrap(), range);
+        assert!(serde_json::from_str::<IpRange>(r#""10.0.0.0/40""#).is_err());
+    }
+
+    #[test]
+    fn detects_broad_ranges() {
+        assert!(range("0.0.0.0/0").is_broad());
+        assert!(range("10.0.0.0/7").is_broad());
+        assert!(!range("10.0.0.0/8").is_broad());
+        assert!(!range("192.0.2.1").is_broad());
+        assert!(range("::/0").is_broad());
+        assert!(range("2001:db8::/31").is_broad());
+        assert!(!range("2001:db8::/32").is_broad());
+    }
+}
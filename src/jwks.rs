@@ -0,0 +1,24 @@
+use crate::chat::{ChatServer, jwks::GetJwks};
+use log::*;
+
+use actix::*;
+use actix_web::{HttpResponse, web};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/.well-known/jwks.json", web::get().to(jwks));
+}
+
+/// Serves the public keys of the authenticator so other services can verify its tokens.
+/// The keys are read from the `ChatServer`, so they follow configuration reloads.
+async fn jwks(srv: web::Data<Addr<ChatServer>>) -> HttpResponse {
+    match srv.send(GetJwks).await {
+        Ok(Some(jwks)) => HttpResponse::Ok()
+            .content_type("application/jwk-set+json")
+            .json(jwks),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(err) => {
+            warn!("Could not read the JWT keys: {}", err);
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
@@ -0,0 +1,623 @@
+use crate::error::*;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use pkcs1::RsaPublicKey;
+use pkcs8::{
+    ObjectIdentifier, PrivateKeyInfo,
+    der::{Decode, Encode},
+    spki::{AlgorithmIdentifierRef, SubjectPublicKeyInfoRef},
+};
+use ring::{rand::SystemRandom, rsa, signature};
+use serde_json::{Value, json};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// 1.3.101.112
+const ED25519: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+/// 1.2.840.10045.3.1.7
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+/// 1.3.132.0.34
+const SECP384R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+
+/// The kinds of keys the JWT algorithms need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyFamily {
+    Hmac,
+    Rsa,
+    Ec,
+    Ed,
+}
+
+impl KeyFamily {
+    fn of(algorithm: Algorithm) -> KeyFamily {
+        use Algorithm::*;
+
+        match algorithm {
+            HS256 | HS384 | HS512 => KeyFamily::Hmac,
+            RS256 | RS384 | RS512 | PS256 | PS384 | PS512 => KeyFamily::Rsa,
+            ES256 | ES384 => KeyFamily::Ec,
+            EdDSA => KeyFamily::Ed,
+        }
+    }
+}
+
+/// The public part of an asymmetric key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PublicKey {
+    /// The big-endian modulus and exponent.
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    /// The uncompressed curve point.
+    Ec {
+        point: Vec<u8>,
+    },
+    Ed {
+        x: Vec<u8>,
+    },
+}
+
+impl PublicKey {
+    fn decoding_key(&self) -> DecodingKey {
+        match self {
+            PublicKey::Rsa { n, e } => DecodingKey::from_rsa_raw_components(n, e),
+            PublicKey::Ec { point } => DecodingKey::from_ec_der(point),
+            PublicKey::Ed { x } => DecodingKey::from_ed_der(x),
+        }
+    }
+
+    /// Returns the key parameters of the JSON Web Key, see RFC 7518 and RFC 8037.
+    fn jwk(&self, algorithm: Algorithm) -> Value {
+        let encode = |bytes: &[u8]| URL_SAFE_NO_PAD.encode(bytes);
+        match self {
+            PublicKey::Rsa { n, e } => json!({
+                "kty": "RSA",
+                "n": encode(n),
+                "e": encode(e),
+            }),
+            PublicKey::Ec { point } => {
+                // The point is 0x04 followed by both coordinates.
+                let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+                let crv = match algorithm {
+                    Algorithm::ES384 => "P-384",
+                    _ => "P-256",
+                };
+                json!({
+                    "kty": "EC",
+                    "crv": crv,
+                    "x": encode(x),
+                    "y": encode(y),
+                })
+            }
+            PublicKey::Ed { x } => json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": encode(x),
+            }),
+        }
+    }
+}
+
+/// The keys tokens are signed and verified with.
+pub struct JwtKey {
+    pub algorithm: Algorithm,
+    /// Missing if only the public key is known.
+    pub encoding: Option<EncodingKey>,
+    pub decoding: DecodingKey,
+    /// Missing for HMAC secrets.
+    public: Option<PublicKey>,
+}
+
+impl JwtKey {
+    /// Loads the keys for `algorithm`.
+    ///
+    /// For HMAC, `key_file` contains the secret.
+    /// Otherwise it contains the private key in PKCS#8 (or PKCS#1 for RSA, SEC1 for ECDSA),
+    /// and `public_key_file` the public key as SubjectPublicKeyInfo (or PKCS#1 for RSA).
+    /// The public key is derived from the private key if it is missing;
+    /// if both are given, they have to match.
+    /// Files are read as PEM if their extension is `pem`, otherwise as DER.
+    pub fn load(
+        algorithm: Algorithm,
+        key_file: Option<&Path>,
+        public_key_file: Option<&Path>,
+    ) -> Result<JwtKey> {
+        let family = KeyFamily::of(algorithm);
+        if family == KeyFamily::Hmac {
+            if public_key_file.is_some() {
+                return Err(invalid_config(
+                    "HMAC algorithms don't use a `public_key_file`",
+                ));
+            }
+            let path =
+                key_file.ok_or_else(|| invalid_config("HMAC algorithms need a `key_file`"))?;
+            let secret = fs::read(path)?;
+            if secret.is_empty() {
+                return Err(invalid_key(path, "the secret is empty"));
+            }
+            return Ok(JwtKey {
+                algorithm,
+                encoding: Some(EncodingKey::from_secret(&secret)),
+                decoding: DecodingKey::from_secret(&secret),
+                public: None,
+            });
+        }
+
+        let private = key_file
+            .map(|path| load_private_key(algorithm, family, path))
+            .transpose()?;
+        let public = public_key_file
+            .map(|path| Ok::<_, Error>((path, load_public_key(algorithm, family, path)?)))
+            .transpose()?;
+
+        let (encoding, public) = match (private, public) {
+            (Some((encoding, derived)), Some((path, public))) => {
+                if derived != public {
+                    return Err(invalid_key(
+                        path,
+                        "the public key doesn't match the private key",
+                    ));
+                }
+                (Some(encoding), public)
+            }
+            (Some((encoding, derived)), None) => (Some(encoding), derived),
+            (None, Some((_, public))) => (None, public),
+            (None, None) => {
+                return Err(invalid_config(format!(
+                    "{:?} needs a `key_file` or a `public_key_file`",
+                    algorithm
+                )));
+            }
+        };
+        Ok(JwtKey {
+            algorithm,
+            encoding,
+            decoding: public.decoding_key(),
+            public: Some(public),
+        })
+    }
+
+    /// Returns the public key as a JSON Web Key for other services to verify tokens with.
+    /// HMAC secrets are never published.
+    pub fn jwk(&self, kid: Option<&str>) -> Option<Value> {
+        let mut jwk = self.public.as_ref()?.jwk(self.algorithm);
+        jwk["use"] = json!("sig");
+        jwk["alg"] = json!(self.algorithm);
+        if let Some(kid) = kid {
+            jwk["kid"] = json!(kid);
+        }
+        Some(jwk)
+    }
+}
+
+/// Reads a key file, returning the PEM label if it is PEM encoded and the DER contents.
+fn read_key(path: &Path) -> Result<(Option<String>, Vec<u8>)> {
+    let data = fs::read(path)?;
+    if path.extension().is_some_and(|ext| ext == "pem") {
+        let pem = pem::parse(&data).map_err(|err| invalid_key(path, err.to_string()))?;
+        Ok((Some(pem.tag().to_string()), pem.into_contents()))
+    } else {
+        Ok((None, data))
+    }
+}
+
+/// Returns the curve the ECDSA `algorithm` uses.
+fn curve(algorithm: Algorithm) -> ObjectIdentifier {
+    match algorithm {
+        Algorithm::ES384 => SECP384R1,
+        _ => SECP256R1,
+    }
+}
+
+fn load_private_key(
+    algorithm: Algorithm,
+    family: KeyFamily,
+    path: &Path,
+) -> Result<(EncodingKey, PublicKey)> {
+    let (label, der) = read_key(path)?;
+    let rejected = |err: ring::error::KeyRejected| {
+        invalid_key(
+            path,
+            format!("not a valid {:?} private key: {}", algorithm, err),
+        )
+    };
+    let invalid = |err: pkcs8::Error| {
+        invalid_key(
+            path,
+            format!("not a valid {:?} private key: {}", algorithm, err),
+        )
+    };
+    // DER files don't tell their format, PKCS#8 is tried first.
+    let pkcs8 = match label.as_deref() {
+        Some("PRIVATE KEY") => {
+            Some(PrivateKeyInfo::from_der(&der).map_err(|err| invalid(err.into()))?)
+        }
+        Some(_) => None,
+        None => PrivateKeyInfo::from_der(&der).ok(),
+    };
+    match (family, label.as_deref()) {
+        (KeyFamily::Rsa, None | Some("PRIVATE KEY" | "RSA PRIVATE KEY")) => {
+            // jsonwebtoken expects PKCS#1, which is wrapped in PKCS#8 keys.
+            let pkcs1 = match pkcs8 {
+                Some(info) => {
+                    info.algorithm
+                        .assert_algorithm_oid(pkcs1::ALGORITHM_OID)
+                        .map_err(|err| invalid(err.into()))?;
+                    info.private_key
+                }
+                None => der.as_slice(),
+            };
+            let key_pair = rsa::KeyPair::from_der(pkcs1).map_err(rejected)?;
+            let components = rsa::PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+            Ok((
+                EncodingKey::from_rsa_der(pkcs1),
+                PublicKey::Rsa {
+                    n: components.n,
+                    e: components.e,
+                },
+            ))
+        }
+        (KeyFamily::Ec, None | Some("PRIVATE KEY" | "EC PRIVATE KEY")) => {
+            // ring only reads PKCS#8, so SEC1 keys are wrapped into it.
+            let der = match pkcs8 {
+                Some(_) => der,
+                None => sec1_to_pkcs8(&der, curve(algorithm)).map_err(invalid)?,
+            };
+            let signing = match algorithm {
+                Algorithm::ES384 => &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+                _ => &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            };
+            let key_pair = signature::EcdsaKeyPair::from_pkcs8(signing, &der, &SystemRandom::new())
+                .map_err(rejected)?;
+            let point = signature::KeyPair::public_key(&key_pair).as_ref().to_vec();
+            Ok((EncodingKey::from_ec_der(&der), PublicKey::Ec { point }))
+        }
+        (KeyFamily::Ed, None | Some("PRIVATE KEY")) => {
+            let key_pair =
+                signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(rejected)?;
+            let x = signature::KeyPair::public_key(&key_pair).as_ref().to_vec();
+            Ok((EncodingKey::from_ed_der(&der), PublicKey::Ed { x }))
+        }
+        (_, Some(label)) => Err(invalid_key(
+            path,
+            format!("expected a {:?} private key, found `{}`", algorithm, label),
+        )),
+        (KeyFamily::Hmac, None) => unreachable!("HMAC secrets aren't private keys"),
+    }
+}
+
+/// Wraps a SEC1 ECPrivateKey on `curve` into a PKCS#8 PrivateKeyInfo.
+fn sec1_to_pkcs8(der: &[u8], curve: ObjectIdentifier) -> pkcs8::Result<Vec<u8>> {
+    let key = sec1::EcPrivateKey::from_der(der)?;
+    if key
+        .parameters
+        .is_some_and(|parameters| parameters.named_curve() != Some(curve))
+    {
+        return Err(pkcs8::Error::ParametersMalformed);
+    }
+    let info = PrivateKeyInfo {
+        algorithm: AlgorithmIdentifierRef {
+            oid: sec1::ALGORITHM_OID,
+            parameters: Some((&curve).into()),
+        },
+        private_key: der,
+        public_key: None,
+    };
+    Ok(info.to_der()?)
+}
+
+fn load_public_key(algorithm: Algorithm, family: KeyFamily, path: &Path) -> Result<PublicKey> {
+    let (label, der) = read_key(path)?;
+    let invalid = || invalid_key(path, format!("not a valid {:?} public key", algorithm));
+
+    if family == KeyFamily::Rsa && label.as_deref() == Some("RSA PUBLIC KEY") {
+        return rsa_public_key(&der).ok_or_else(invalid);
+    }
+    if !matches!(label.as_deref(), None | Some("PUBLIC KEY")) {
+        return Err(invalid_key(
+            path,
+            format!(
+                "expected a {:?} public key, found `{}`",
+                algorithm,
+                label.unwrap_or_default()
+            ),
+        ));
+    }
+
+    let Ok(info) = SubjectPublicKeyInfoRef::from_der(&der) else {
+        // DER files may contain PKCS#1 RSA keys as well.
+        return match family {
+            KeyFamily::Rsa => rsa_public_key(&der).ok_or_else(invalid),
+            _ => Err(invalid()),
+        };
+    };
+    let oid = info.algorithm.oid;
+    let key = info.subject_public_key.as_bytes().ok_or_else(invalid)?;
+    match family {
+        KeyFamily::Rsa if oid == pkcs1::ALGORITHM_OID => rsa_public_key(key).ok_or_else(invalid),
+        KeyFamily::Ec if oid == sec1::ALGORITHM_OID => {
+            let length = match algorithm {
+                Algorithm::ES384 => 97,
+                _ => 65,
+            };
+            let curve_matches = info.algorithm.parameters_oid().ok() == Some(curve(algorithm));
+            if !curve_matches || key.len() != length || key[0] != 0x04 {
+                return Err(invalid_key(
+                    path,
+                    format!("the curve doesn't match {:?}", algorithm),
+                ));
+            }
+            Ok(PublicKey::Ec {
+                point: key.to_vec(),
+            })
+        }
+        KeyFamily::Ed if oid == ED25519 && key.len() == 32 => Ok(PublicKey::Ed { x: key.to_vec() }),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a PKCS#1 RSAPublicKey.
+fn rsa_public_key(der: &[u8]) -> Option<PublicKey> {
+    let key = RsaPublicKey::from_der(der).ok()?;
+    let (n, e) = (key.modulus.as_bytes(), key.public_exponent.as_bytes());
+    if n.iter().all(|&byte| byte == 0) || e.iter().all(|&byte| byte == 0) {
+        return None;
+    }
+    Some(PublicKey::Rsa {
+        n: n.to_vec(),
+        e: e.to_vec(),
+    })
+}
+
+fn invalid_key(path: &Path, message: impl Into<String>) -> Error {
+    Error::InvalidKey {
+        path: PathBuf::from(path),
+        message: message.into(),
+    }
+}
+
+fn invalid_config(message: impl Into<String>) -> Error {
+    Error::InvalidConfig {
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{Header, Validation};
+    use serde_json::json;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/keys")
+            .join(name)
+    }
+
+    fn load(algorithm: Algorithm, key: Option<&str>, public: Option<&str>) -> Result<JwtKey> {
+        JwtKey::load(
+            algorithm,
+            key.map(fixture).as_deref(),
+            public.map(fixture).as_deref(),
+        )
+    }
+
+    /// Writes `data` to a temporary file, so broken keys can be loaded.
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("axochat-key-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// Signs a token with `signer` and verifies it with `verifier`.
+    fn round_trip(signer: &JwtKey, verifier: &JwtKey) -> bool {
+        let claims = json!({ "exp": 4_000_000_000u64 });
+        let token = jsonwebtoken::encode(
+            &Header::new(signer.algorithm),
+            &claims,
+            signer.encoding.as_ref().unwrap(),
+        )
+        .unwrap();
+        jsonwebtoken::decode::<serde_json::Value>(
+            &token,
+            &verifier.decoding,
+            &Validation::new(verifier.algorithm),
+        )
+        .is_ok()
+    }
+
+    const KEYS: &[(Algorithm, &[&str], &[&str])] = &[
+        (
+            Algorithm::RS256,
+            &["rsa.pem", "rsa.der", "rsa_pkcs1.pem", "rsa_pkcs1.der"],
+            &[
+                "rsa_pub.pem",
+                "rsa_pub.der",
+                "rsa_pub_pkcs1.pem",
+                "rsa_pub_pkcs1.der",
+            ],
+        ),
+        (
+            Algorithm::PS384,
+            &["rsa.pem", "rsa_pkcs1.der"],
+            &["rsa_pub.der", "rsa_pub_pkcs1.pem"],
+        ),
+        (
+            Algorithm::ES256,
+            &["ec256.pem", "ec256.der", "ec256_sec1.pem", "ec256_sec1.der"],
+            &["ec256_pub.pem", "ec256_pub.der"],
+        ),
+        (
+            Algorithm::ES384,
+            &["ec384.pem", "ec384.der", "ec384_sec1.pem", "ec384_sec1.der"],
+            &["ec384_pub.pem", "ec384_pub.der"],
+        ),
+        (
+            Algorithm::EdDSA,
+            &["ed.pem", "ed.der"],
+            &["ed_pub.pem", "ed_pub.der"],
+        ),
+    ];
+
+    #[test]
+    fn loads_every_format() {
+        for &(algorithm, private_keys, public_keys) in KEYS {
+            for private in private_keys {
+                let signer = load(algorithm, Some(private), None)
+                    .unwrap_or_else(|err| panic!("{:?} {}: {}", algorithm, private, err));
+                assert!(round_trip(&signer, &signer), "{:?} {}", algorithm, private);
+
+                for public in public_keys {
+                    let verifier = load(algorithm, None, Some(public))
+                        .unwrap_or_else(|err| panic!("{:?} {}: {}", algorithm, public, err));
+                    assert!(verifier.encoding.is_none());
+                    assert!(round_trip(&signer, &verifier), "{:?} {}", algorithm, public);
+                    assert_eq!(signer.jwk(None), verifier.jwk(None));
+
+                    load(algorithm, Some(private), Some(public))
+                        .unwrap_or_else(|err| panic!("{:?} {}: {}", algorithm, public, err));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loads_hmac_secrets() {
+        let key = load(Algorithm::HS256, Some("hs.key"), None).unwrap();
+        assert!(round_trip(&key, &key));
+        assert!(key.jwk(Some("secret")).is_none());
+
+        assert!(load(Algorithm::HS256, Some("empty.key"), None).is_err());
+        assert!(load(Algorithm::HS256, None, None).is_err());
+        assert!(load(Algorithm::HS256, Some("hs.key"), Some("rsa_pub.pem")).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_keys() {
+        let cases = [
+            (Algorithm::RS256, Some("ec256.pem"), None),
+            (Algorithm::RS256, Some("ed.der"), None),
+            (Algorithm::RS256, None, Some("ec256_pub.der")),
+            (Algorithm::ES256, Some("rsa.der"), None),
+            (Algorithm::ES256, Some("ec384.pem"), None),
+            (Algorithm::ES384, Some("ec256.der"), None),
+            (Algorithm::ES256, None, Some("ec384_pub.pem")),
+            (Algorithm::ES384, None, Some("ec256_pub.der")),
+            (Algorithm::ES256, None, Some("rsa_pub_pkcs1.pem")),
+            (Algorithm::EdDSA, Some("ec256.pem"), None),
+            (Algorithm::EdDSA, None, Some("rsa_pub.der")),
+            (Algorithm::ES384, Some("ec256_sec1.pem"), None),
+            (Algorithm::ES256, Some("ec384_sec1.der"), None),
+            (Algorithm::RS256, Some("ec256_sec1.pem"), None),
+            (Algorithm::EdDSA, Some("ec256_sec1.der"), None),
+            (Algorithm::RS256, Some("rsa.pem"), Some("rsa_other_pub.pem")),
+            (Algorithm::ES256, Some("ec256.pem"), Some("ec384_pub.pem")),
+            (Algorithm::RS256, Some("rsa_pub.pem"), None),
+            (Algorithm::RS256, None, Some("rsa.pem")),
+            (Algorithm::RS256, None, None),
+        ];
+        for (algorithm, private, public) in cases {
+            assert!(
+                load(algorithm, private, public).is_err(),
+                "{:?} {:?} {:?}",
+                algorithm,
+                private,
+                public
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_keys() {
+        for &(algorithm, private_keys, public_keys) in KEYS {
+            let der_files = private_keys
+                .iter()
+                .map(|name| (name, true))
+                .chain(public_keys.iter().map(|name| (name, false)))
+                .filter(|(name, _)| name.ends_with(".der"));
+            for (name, private) in der_files {
+                let der = fs::read(fixture(name)).unwrap();
+                for length in 0..der.len() {
+                    let path = temp_file(&format!("truncated-{}", name), &der[..length]);
+                    let res = if private {
+                        JwtKey::load(algorithm, Some(&path), None)
+                    } else {
+                        JwtKey::load(algorithm, None, Some(&path))
+                    };
+                    assert!(res.is_err(), "{} truncated to {}", name, length);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_oids() {
+        let der = fs::read(fixture("ec256_pub.der")).unwrap();
+        let ec_public_key = sec1::ALGORITHM_OID.as_bytes();
+        let position = der
+            .windows(ec_public_key.len())
+            .position(|window| window == ec_public_key)
+            .unwrap();
+
+        // Replaces the algorithm with an unknown one of the same length.
+        let mut wrong_oid = der.clone();
+        wrong_oid.splice(
+            position..position + ec_public_key.len(),
+            pkcs1::ALGORITHM_OID.as_bytes()[..7].iter().copied(),
+        );
+        let path = temp_file("wrong-oid.der", &wrong_oid);
+        assert!(JwtKey::load(Algorithm::ES256, None, Some(&path)).is_err());
+        assert!(JwtKey::load(Algorithm::RS256, None, Some(&path)).is_err());
+
+        let ed = fs::read(fixture("ed_pub.der")).unwrap();
+        let path = temp_file("ed-as-ec.der", &ed);
+        assert!(JwtKey::load(Algorithm::ES256, None, Some(&path)).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_structures() {
+        let cases: [(Algorithm, &[u8]); 4] = [
+            // A SubjectPublicKeyInfo whose bit string has unused bits.
+            (
+                Algorithm::EdDSA,
+                &[
+                    0x30, 0x0a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x01, 0x01,
+                ],
+            ),
+            // A SubjectPublicKeyInfo without a key.
+            (
+                Algorithm::EdDSA,
+                &[0x30, 0x07, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70],
+            ),
+            // RSA keys with a zero modulus or without an exponent.
+            (
+                Algorithm::RS256,
+                &[0x30, 0x06, 0x02, 0x01, 0x00, 0x02, 0x01, 0x03],
+            ),
+            (Algorithm::RS256, &[0x30, 0x03, 0x02, 0x01, 0x05]),
+        ];
+        for (index, (algorithm, der)) in cases.into_iter().enumerate() {
+            let path = temp_file(&format!("malformed-{}.der", index), der);
+            assert!(
+                JwtKey::load(algorithm, None, Some(&path)).is_err(),
+                "{}",
+                index
+            );
+        }
+
+        // A PrivateKeyInfo without the private key.
+        let path = temp_file(
+            "malformed-private.der",
+            &[0x30, 0x05, 0x02, 0x01, 0x00, 0x30, 0x00],
+        );
+        for algorithm in [Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA] {
+            assert!(JwtKey::load(algorithm, Some(&path), None).is_err());
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use crate::config::NetConfig;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The window of `net.connections_per_minute`.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits the open connections and the rate of new connections per address.
+/// IPv6 addresses are counted per `net.ipv6_prefix` block.
+/// Connections are counted from the moment they are accepted
+/// until their `ConnectionPermit` is dropped.
+pub struct ConnectionLimiter {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    connections_per_minute: Option<usize>,
+    ipv6_prefix: u8,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    open: usize,
+    addresses: HashMap<IpAddr, AddressState>,
+    last_cleanup: Instant,
+}
+
+#[derive(Default)]
+struct AddressState {
+    open: usize,
+    /// The instants of the connections accepted in the last minute.
+    recent: VecDeque<Instant>,
+}
+
+impl AddressState {
+    fn expire(&mut self, now: Instant) {
+        while let Some(time) = self.recent.front() {
+            if now.duration_since(*time) < WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    fn is_unused(&self) -> bool {
+        self.open == 0 && self.recent.is_empty()
+    }
+}
+
+/// The reason a connection was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    MaxConnections,
+    MaxConnectionsPerIp,
+    ConnectionsPerMinute,
+}
+
+impl LimitExceeded {
+    /// Returns the name used for the metrics label.
+    pub fn kind(self) -> &'static str {
+        match self {
+            LimitExceeded::MaxConnections => "max_connections",
+            LimitExceeded::MaxConnectionsPerIp => "max_connections_per_ip",
+            LimitExceeded::ConnectionsPerMinute => "connections_per_minute",
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::MaxConnections => write!(f, "the server is full"),
+            LimitExceeded::MaxConnectionsPerIp => {
+                write!(f, "too many connections from this address")
+            }
+            LimitExceeded::ConnectionsPerMinute => {
+                write!(f, "too many new connections from this address")
+            }
+        }
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &NetConfig) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            connections_per_minute: config.connections_per_minute,
+            ipv6_prefix: config.ipv6_prefix,
+            state: Mutex::new(LimiterState {
+                open: 0,
+                addresses: HashMap::new(),
+                last_cleanup: Instant::now(),
+            }),
+        }
+    }
+
+    /// Counts a new connection from `ip` if no limit is exceeded.
+    /// Connections without a known address are only subject to `max_connections`.
+    pub fn acquire(
+        self: &Arc<Self>,
+        ip: Option<IpAddr>,
+    ) -> Result<ConnectionPermit, LimitExceeded> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("connection limiter lock poisoned");
+
+        if now.duration_since(state.last_cleanup) >= WINDOW {
+            state.addresses.retain(|_, address| {
+                address.expire(now);
+                !address.is_unused()
+            });
+            state.last_cleanup = now;
+        }
+
+        if self.max_connections.is_some_and(|max| state.open >= max) {
+            return Err(LimitExceeded::MaxConnections);
+        }
+
+        if let Some(ip) = ip {
+            let address = state.addresses.entry(self.group(ip)).or_default();
+            address.expire(now);
+            if self
+                .max_connections_per_ip
+                .is_some_and(|max| address.open >= max)
+            {
+                return Err(LimitExceeded::MaxConnectionsPerIp);
+            }
+            if self
+                .connections_per_minute
+                .is_some_and(|max| address.recent.len() >= max)
+            {
+                return Err(LimitExceeded::ConnectionsPerMinute);
+            }
+            address.open += 1;
+            address.recent.push_back(now);
+        }
+        state.open += 1;
+
+        Ok(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut state = self.state.lock().expect("connection limiter lock poisoned");
+        state.open -= 1;
+        if let Some(ip) = ip {
+            let group = self.group(ip);
+            if let Some(address) = state.addresses.get_mut(&group) {
+                address.open -= 1;
+                if address.is_unused() {
+                    state.addresses.remove(&group);
+                }
+            }
+        }
+    }
+
+    /// Returns the address the connections from `ip` are counted under:
+    /// IPv4 addresses themselves, and the network of IPv6 addresses.
+    fn group(&self, ip: IpAddr) -> IpAddr {
+        match ip.to_canonical() {
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.ipv6_prefix as u32)
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+            ip => ip,
+        }
+    }
+}
+
+/// An accepted connection; it stops being counted once this is dropped.
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionPermit {
+    /// Returns the address of the peer, if it is known.
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ip
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_connections_per_ip: usize) -> Arc<ConnectionLimiter> {
+        Arc::new(ConnectionLimiter::new(&NetConfig {
+            max_connections_per_ip: Some(max_connections_per_ip),
+            ..NetConfig::default()
+        }))
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn groups_ipv6_addresses_by_prefix() {
+        let limiter = limiter(2);
+        let _first = limiter.acquire(ip("2001:db8::1")).unwrap();
+        let second = limiter.acquire(ip("2001:db8::ffff:2")).unwrap();
+        assert_eq!(
+            limiter.acquire(ip("2001:db8::3")).err(),
+            Some(LimitExceeded::MaxConnectionsPerIp)
+        );
+        // Another /64 block and IPv4 addresses are counted separately.
+        let _other = limiter.acquire(ip("2001:db8:0:1::1")).unwrap();
+        let _v4 = limiter.acquire(ip("192.0.2.1")).unwrap();
+        let _v4 = limiter.acquire(ip("192.0.2.2")).unwrap();
+
+        drop(second);
+        assert!(limiter.acquire(ip("2001:db8::3")).is_ok());
+    }
+}
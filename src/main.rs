@@ -1,14 +1,30 @@
+mod admin;
+mod audit;
 mod auth;
 mod chat;
 mod config;
 mod error;
+mod health;
+mod history;
+mod ip;
+mod jwks;
+mod key;
+mod limit;
 mod message;
+mod metrics;
 mod moderation;
+mod proxy;
+mod storage;
+mod time;
 
 use config::Config;
 use error::*;
 use log::*;
 use rustls::pki_types::PrivateKeyDer;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use structopt::*;
 
 use actix::*;
@@ -61,6 +77,12 @@ async fn main() -> Result<()> {
                     Err(ClientError::NotSupported.into())
                 }
             }?;
+            if !auth.can_sign() {
+                eprintln!(
+                    "Please add a private key as `key_file` to the `auth` segment or set `auth.active_key`."
+                );
+                return Err(ClientError::NotSupported.into());
+            }
             let token = auth.new_token(auth::UserInfo {
                 name,
                 uuid: uuid.unwrap_or_else(|| Uuid::from_u128(0)),
@@ -72,83 +94,200 @@ async fn main() -> Result<()> {
 }
 
 async fn start_server(config: Config) -> Result<()> {
+    let metrics = Arc::new(metrics::Metrics::default());
     let server_config = config.clone();
-    let server = chat::ChatServer::new(server_config).start();
+    let server = chat::ChatServer::new(server_config, metrics.clone()).start();
 
     let server_data = web::Data::new(server);
+    let metrics_data = web::Data::from(metrics);
+    let health = web::Data::new(health::Health::default());
+    let net_data = web::Data::new(config.net.clone());
+    let limiter = web::Data::new(limit::ConnectionLimiter::new(&config.net));
+    let mut handles = Vec::new();
+    // Metrics are served at the main address unless they have their own.
+    let serve_metrics = config.net.metrics && config.net.metrics_address.is_none();
     let address = config.net.address.to_string();
+    let admin_token = match &config.admin {
+        Some(cfg) => Some(web::Data::new(admin::AdminToken::new(cfg)?)),
+        None => None,
+    };
+
+    if let Some(metrics_address) = config.net.metrics_address.filter(|_| config.net.metrics) {
+        let server_data = server_data.clone();
+        let metrics_data = metrics_data.clone();
+        let metrics_server = HttpServer::new(move || {
+            App::new()
+                .app_data(server_data.clone())
+                .app_data(metrics_data.clone())
+                .route("/metrics", web::get().to(metrics::metrics_route))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(metrics_address)?
+        .run();
+        info!("Serving metrics at {}", metrics_address);
+        handles.push(metrics_server.handle());
+        actix_web::rt::spawn(metrics_server);
+    }
 
-    let mut server = HttpServer::new(move || {
+    let shutdown_health = health.clone();
+    let shutdown_server = server_data.get_ref().clone();
+    let app = move || {
         App::new()
             .app_data(server_data.clone())
+            .app_data(metrics_data.clone())
+            .app_data(health.clone())
+            .app_data(net_data.clone())
+            .app_data(limiter.clone())
             .service(web::resource("/ws").to(chat::chat_route))
-    });
+            .configure(health::configure)
+            .configure(jwks::configure)
+            .configure(|cfg| admin::configure(cfg, admin_token.clone()))
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(metrics::metrics_route));
+                }
+            })
+    };
 
-    if let (Some(cert), Some(key)) = (config.net.cert_file, config.net.key_file) {
-        #[cfg(all(feature = "openssl-tls", feature = "rustls-tls"))]
+    let server = if config.net.proxy_protocol {
+        info!("Expecting the PROXY protocol at {}", config.net.address);
+        proxy::bind(config.net.address, config.net.trusted_proxies.clone(), app)?
+    } else {
+        let mut server = HttpServer::new(app);
+        if let (Some(cert), Some(key)) = (config.net.cert_file.clone(), config.net.key_file.clone())
         {
-            compile_error!("Can't enable both the `openssl-tls` and the `rustls-tls` feature.")
+            #[cfg(all(feature = "openssl-tls", feature = "rustls-tls"))]
+            {
+                compile_error!("Can't enable both the `openssl-tls` and the `rustls-tls` feature.")
+            }
+
+            #[cfg(feature = "openssl-tls")]
+            {
+                let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+                builder.set_certificate_chain_file(&cert)?;
+                let ft = match key.extension() {
+                    Some(ext) if ext == "pem" => SslFiletype::PEM,
+                    _ => SslFiletype::ASN1,
+                };
+                builder.set_private_key_file(&key, ft)?;
+
+                server = server.bind_openssl(address, builder)?;
+            }
+
+            #[cfg(feature = "rustls-tls")]
+            {
+                info!(
+                    "Loading TLS certificate from {:?} and key from {:?}",
+                    cert, key
+                );
+
+                // Read cert and key files with proper mutability
+                let mut cert_file = BufReader::new(File::open(&cert)?);
+                let mut key_file = BufReader::new(File::open(&key)?);
+
+                // Load certificate chain and key
+                let cert_chain = certs(&mut cert_file).map(|cert| cert.unwrap()).collect();
+
+                let Some(key) = pkcs8_private_keys(&mut key_file)
+                    .next()
+                    .transpose()
+                    .map_err(|err| Error::IO { source: err })?
+                else {
+                    return Err(Error::RustTLSNoMsg);
+                };
+
+                // Build rustls server configuration
+                let config = ServerConfig::builder()
+                    .with_no_client_auth()
+                    .with_single_cert(cert_chain, PrivateKeyDer::Pkcs8(key))
+                    .map_err(|err| Error::RustTLS { source: err })?;
+
+                // Special hack to make rustls versions compatible with actix-web
+                // We use unsafe to cast our ServerConfig to the version expected by actix-web
+                use std::mem;
+                let config_ptr = Box::into_raw(Box::new(config));
+                let actix_rustls_config =
+                    unsafe { mem::transmute::<*mut ServerConfig, *mut _>(config_ptr) };
+
+                server =
+                    server.bind_rustls(address, unsafe { *Box::from_raw(actix_rustls_config) })?;
+            }
+
+            #[cfg(not(any(feature = "openssl-tls", feature = "rustls-tls")))]
+            {
+                server = server.bind(address)?;
+            }
+        } else {
+            server = server.bind(address)?;
         }
+        server.disable_signals().run()
+    };
 
-        #[cfg(feature = "openssl-tls")]
-        {
-            let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-            builder.set_certificate_chain_file(&cert)?;
-            let ft = match key.extension() {
-                Some(ext) if ext == "pem" => SslFiletype::PEM,
-                _ => SslFiletype::ASN1,
-            };
-            builder.set_private_key_file(&key, ft)?;
-
-            server = server.bind_openssl(address, builder)?;
+    info!("Started server at {}", config.net.address);
+    handles.push(server.handle());
+    actix_web::rt::spawn(async move {
+        wait_for_shutdown_signal().await;
+        info!("Shutting down.");
+        shutdown_health.start_shutdown();
+        actix_web::rt::time::sleep(*config.net.shutdown_delay).await;
+
+        for handle in &handles {
+            handle.pause().await;
+        }
+        let shutdown = chat::shutdown::Shutdown {
+            reason: String::from("The server is shutting down."),
+            reconnect_after: config.net.reconnect_after,
+        };
+        if let Err(err) = shutdown_server.send(shutdown).await {
+            error!("Could not notify clients of shutdown: {}", err);
         }
+        drain(&shutdown_server, *config.net.drain_timeout).await;
 
-        #[cfg(feature = "rustls-tls")]
-        {
-            info!(
-                "Loading TLS certificate from {:?} and key from {:?}",
-                cert, key
-            );
-
-            // Read cert and key files with proper mutability
-            let mut cert_file = BufReader::new(File::open(&cert)?);
-            let mut key_file = BufReader::new(File::open(&key)?);
-
-            // Load certificate chain and key
-            let cert_chain = certs(&mut cert_file).map(|cert| cert.unwrap()).collect();
-
-            let Some(key) = pkcs8_private_keys(&mut key_file)
-                .next()
-                .transpose()
-                .map_err(|err| Error::IO { source: err })?
-            else {
-                return Err(Error::RustTLSNoMsg);
-            };
-
-            // Build rustls server configuration
-            let config = ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(cert_chain, PrivateKeyDer::Pkcs8(key))
-                .map_err(|err| Error::RustTLS { source: err })?;
-
-            // Special hack to make rustls versions compatible with actix-web
-            // We use unsafe to cast our ServerConfig to the version expected by actix-web
-            use std::mem;
-            let config_ptr = Box::into_raw(Box::new(config));
-            let actix_rustls_config = unsafe { mem::transmute(config_ptr) };
-
-            server = server.bind_rustls(address, unsafe { *Box::from_raw(actix_rustls_config) })?;
+        for handle in handles {
+            handle.stop(false).await;
         }
+    });
+    server.await?;
+    Ok(())
+}
 
-        #[cfg(not(any(feature = "openssl-tls", feature = "rustls-tls")))]
-        {
-            server = server.bind(address)?;
+/// Waits until every connection is closed or the timeout has passed.
+async fn drain(server: &Addr<chat::ChatServer>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match server.send(chat::admin::GetUserCount).await {
+            Ok(count) if count.connections == 0 => return,
+            Ok(_) => actix_web::rt::time::sleep(Duration::from_millis(100)).await,
+            Err(_) => return,
         }
-    } else {
-        server = server.bind(address)?;
     }
+    warn!("Not every connection was closed within {:?}.", timeout);
+}
 
-    info!("Started server at {}", config.net.address);
-    server.run().await?;
-    Ok(())
+/// Waits until the process is asked to terminate.
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{SignalKind, signal};
+        use std::{future, task::Poll};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+        let mut interrupt = signal(SignalKind::interrupt()).expect("could not listen for SIGINT");
+        future::poll_fn(|cx| {
+            if terminate.poll_recv(cx).is_ready() || interrupt.poll_recv(cx).is_ready() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    #[cfg(not(unix))]
+    {
+        if let Err(err) = actix_rt::signal::ctrl_c().await {
+            error!("Could not listen for Ctrl-C: {}", err);
+        }
+    }
 }
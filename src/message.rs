@@ -16,6 +16,11 @@ impl RateLimiter {
         }
     }
 
+    /// Replaces the limits, keeping the messages sent so far.
+    pub fn set_config(&mut self, cfg: MsgConfig) {
+        self.cfg = cfg;
+    }
+
     /// Returns if a new message in this instant would be rate limited.
     /// If not, then it registers the new message instant.
     pub fn check_new_message(&mut self, message: String) -> bool {
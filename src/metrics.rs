@@ -0,0 +1,223 @@
+use crate::chat::{ChatServer, admin::GetUserCount};
+use log::*;
+
+use actix::*;
+use actix_web::{HttpResponse, web};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// The upper bounds in seconds of the Mojang authentication latency buckets.
+const AUTH_DURATION_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counters exported in the Prometheus text format at `/metrics`.
+/// The gauges are taken from the `ChatServer` when the metrics are scraped.
+#[derive(Default)]
+pub struct Metrics {
+    packets_received: LabeledCounter,
+    client_errors: LabeledCounter,
+    connections_rejected: LabeledCounter,
+    messages_broadcast: AtomicU64,
+    private_messages_delivered: AtomicU64,
+    rate_limited: AtomicU64,
+    mojang_auth_failures: AtomicU64,
+    mojang_auth_duration: Histogram,
+}
+
+impl Metrics {
+    pub fn packet_received(&self, kind: &'static str) {
+        self.packets_received.inc(kind);
+    }
+
+    pub fn client_error(&self, kind: &'static str) {
+        self.client_errors.inc(kind);
+    }
+
+    pub fn connection_rejected(&self, reason: &'static str) {
+        self.connections_rejected.inc(reason);
+    }
+
+    pub fn message_broadcast(&self) {
+        self.messages_broadcast.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn private_message_delivered(&self) {
+        self.private_messages_delivered
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a request to the Mojang session server.
+    pub fn mojang_auth(&self, duration: Duration, success: bool) {
+        self.mojang_auth_duration.observe(duration);
+        if !success {
+            self.mojang_auth_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn render(&self, connections: u32, logged_in: u32) -> String {
+        let mut out = String::new();
+        gauge(
+            &mut out,
+            "axochat_connections",
+            "Open WebSocket connections.",
+            connections,
+        );
+        gauge(&mut out, "axochat_users", "Logged in users.", logged_in);
+        self.packets_received.render(
+            &mut out,
+            "axochat_packets_received_total",
+            "Packets received by type.",
+            "packet",
+        );
+        self.client_errors.render(
+            &mut out,
+            "axochat_client_errors_total",
+            "Errors sent to clients by type.",
+            "error",
+        );
+        self.connections_rejected.render(
+            &mut out,
+            "axochat_connections_rejected_total",
+            "Connections rejected by a connection limit.",
+            "limit",
+        );
+        counter(
+            &mut out,
+            "axochat_messages_broadcast_total",
+            "Messages sent to a channel.",
+            &self.messages_broadcast,
+        );
+        counter(
+            &mut out,
+            "axochat_private_messages_delivered_total",
+            "Private messages delivered to a connection.",
+            &self.private_messages_delivered,
+        );
+        counter(
+            &mut out,
+            "axochat_rate_limited_total",
+            "Messages rejected by the rate limiter.",
+            &self.rate_limited,
+        );
+        counter(
+            &mut out,
+            "axochat_mojang_auth_failures_total",
+            "Failed requests to the Mojang session server.",
+            &self.mojang_auth_failures,
+        );
+        self.mojang_auth_duration.render(
+            &mut out,
+            "axochat_mojang_auth_duration_seconds",
+            "Latency of requests to the Mojang session server.",
+        );
+        out
+    }
+}
+
+#[derive(Default)]
+struct LabeledCounter(Mutex<BTreeMap<&'static str, u64>>);
+
+impl LabeledCounter {
+    fn inc(&self, label: &'static str) {
+        *self
+            .0
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(label)
+            .or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, label: &str) {
+        header(out, name, help, "counter");
+        for (value, count) in self.0.lock().expect("metrics lock poisoned").iter() {
+            let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, count);
+        }
+    }
+}
+
+struct Histogram {
+    buckets: [AtomicU64; AUTH_DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(AUTH_DURATION_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        for (bucket, bound) in self.buckets.iter().zip(AUTH_DURATION_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u32) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn metrics_route(
+    metrics: web::Data<Metrics>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> actix_web::Result<HttpResponse> {
+    let count = srv.send(GetUserCount).await.map_err(|err| {
+        warn!("Could not reach chat server: {}", err);
+        actix_web::error::ErrorServiceUnavailable("chat server unavailable")
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render(count.connections, count.logged_in)))
+}
@@ -1,87 +1,700 @@
 use crate::config::ModConfig;
 use crate::error::*;
-use std::collections::HashSet;
+use crate::ip::IpRange;
+use crate::storage::{self, SanctionKind, Storage, UserRecord};
+use crate::time::unix_time;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
-    path::Path,
+    fmt,
+    fs::{self, File},
+    hash::Hash,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime},
 };
 use uuid::Uuid;
 
+/// An action which can be granted to users by their roles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Ban,
+    Mute,
+    Kick,
+    ViewUserCount,
+    Broadcast,
+    ManageRoles,
+    ViewAuditLog,
+    ReloadConfig,
+    ViewAlts,
+}
+
+impl Permission {
+    pub const ALL: &'static [Permission] = &[
+        Permission::Ban,
+        Permission::Mute,
+        Permission::Kick,
+        Permission::ViewUserCount,
+        Permission::Broadcast,
+        Permission::ManageRoles,
+        Permission::ViewAuditLog,
+        Permission::ReloadConfig,
+        Permission::ViewAlts,
+    ];
+}
+
+/// The role users listed in the legacy moderators file have.
+pub const MODERATOR_ROLE: &str = "moderator";
+
+/// A restriction placed upon a user, like a ban.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sanction {
+    pub user: Uuid,
+    /// The moderator who issued this sanction.
+    pub issuer: Option<Uuid>,
+    pub reason: Option<String>,
+    /// The unix timestamp in seconds at which the sanction was issued.
+    pub created: u64,
+    /// The unix timestamp in seconds at which the sanction ends.
+    /// If it is `None`, the sanction is permanent.
+    pub until: Option<u64>,
+}
+
+impl Sanction {
+    fn new(
+        user: Uuid,
+        issuer: Option<Uuid>,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> Sanction {
+        let created = unix_time();
+        Sanction {
+            user,
+            issuer,
+            reason,
+            created,
+            until: duration.map(|duration| expiry(created, duration)),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
+
+/// Returns the timestamp `duration` after `created`.
+/// Absurdly long durations end at the latest time every storage backend can store.
+fn expiry(created: u64, duration: Duration) -> u64 {
+    created
+        .saturating_add(duration.as_secs())
+        .min(i64::MAX as u64)
+}
+
+/// A ban of every connection from an IP address or range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpBan {
+    pub range: IpRange,
+    /// The moderator who issued this ban.
+    pub issuer: Option<Uuid>,
+    pub reason: Option<String>,
+    /// The unix timestamp in seconds at which the ban was issued.
+    pub created: u64,
+    /// The unix timestamp in seconds at which the ban ends.
+    /// If it is `None`, the ban is permanent.
+    pub until: Option<u64>,
+}
+
+impl IpBan {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
+
+/// Another user who has logged in from an address of a user.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alt {
+    pub uuid: Uuid,
+    /// The name the user last logged in with.
+    pub name: Option<String>,
+    /// The addresses both users have logged in from.
+    pub addresses: Vec<IpAddr>,
+    /// The unix timestamp in seconds of the last login from one of these addresses.
+    pub last_seen: u64,
+    pub banned: bool,
+}
+
 pub struct Moderation {
     config: ModConfig,
+    storage: Box<dyn Storage>,
     moderators: HashSet<Uuid>,
-    banned: HashSet<Uuid>,
+    roles: HashMap<Uuid, BTreeSet<String>>,
+    banned: HashMap<Uuid, Sanction>,
+    muted: HashMap<Uuid, Sanction>,
+    ip_banned: HashMap<IpRange, IpBan>,
+    /// The modification times of the watched files when they were last read.
+    modified: HashMap<PathBuf, SystemTime>,
 }
 
 impl Moderation {
     pub fn new(config: ModConfig) -> Result<Moderation> {
-        let moderators = read_ids(&config.moderators)?;
-        let banned = read_ids(&config.banned)?;
-        Ok(Moderation {
+        let storage = storage::open(&config)?;
+        let moderators = storage.moderators()?;
+        let roles = storage.role_assignments()?;
+        let banned = storage.sanctions(SanctionKind::Ban)?;
+        let muted = storage.sanctions(SanctionKind::Mute)?;
+        let ip_banned = storage.ip_bans()?;
+        let mut moderation = Moderation {
             config,
+            storage,
             moderators,
+            roles,
             banned,
-        })
+            muted,
+            ip_banned,
+            modified: HashMap::new(),
+        };
+        moderation.modified = moderation.modification_times();
+        Ok(moderation)
     }
 
-    pub fn is_moderator(&self, user: &Uuid) -> bool {
-        self.moderators.contains(user)
+    /// Replaces the configuration, e.g. the roles, and reloads the moderation files.
+    /// The storage backend keeps using the files it was opened with.
+    /// Returns the users which are newly banned.
+    pub fn set_config(&mut self, config: ModConfig) -> Vec<Uuid> {
+        self.config = config;
+        self.reload()
     }
 
-    /// Ban user if user is not a moderator.
-    pub fn ban(&mut self, user: &Uuid) -> Result<()> {
-        if self.is_moderator(user) {
-            Err(ClientError::NotPermitted.into())
+    /// Reloads the moderation state if one of the watched files changed since it was last read.
+    /// Returns the users which are newly banned.
+    pub fn reload_if_changed(&mut self) -> Vec<Uuid> {
+        if self.modification_times() == self.modified {
+            Vec::new()
         } else {
-            if self.banned.insert(*user) {
-                let mut file = OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(&self.config.banned)?;
+            self.reload()
+        }
+    }
+
+    /// Re-reads the moderators, role assignments, bans, mutes and IP bans.
+    /// Each part which can't be read, e.g. because of a malformed line, keeps its current state.
+    /// Returns the users which are newly banned.
+    pub fn reload(&mut self) -> Vec<Uuid> {
+        self.modified = self.modification_times();
 
-                writeln!(file, "{}", user.hyphenated())?;
+        match self.storage.moderators() {
+            Ok(moderators) => {
+                log_diff("moderators", &self.moderators, &moderators);
+                self.moderators = moderators;
             }
+            Err(err) => warn!("Keeping current moderators, reload failed: {}", err),
+        }
 
+        match self.storage.role_assignments() {
+            Ok(roles) => {
+                let users: HashSet<&Uuid> = self.roles.keys().chain(roles.keys()).collect();
+                for user in users {
+                    let (old, new) = (self.roles.get(user), roles.get(user));
+                    if old != new {
+                        info!("Roles of `{}` changed from {:?} to {:?}.", user, old, new);
+                    }
+                }
+                self.roles = roles;
+            }
+            Err(err) => warn!("Keeping current role assignments, reload failed: {}", err),
+        }
+
+        let banned = match self.storage.sanctions(SanctionKind::Ban) {
+            Ok(banned) => {
+                let added = log_diff("bans", &key_set(&self.banned), &key_set(&banned));
+                self.banned = banned;
+                added
+            }
+            Err(err) => {
+                warn!("Keeping current bans, reload failed: {}", err);
+                Vec::new()
+            }
+        };
+
+        match self.storage.sanctions(SanctionKind::Mute) {
+            Ok(muted) => {
+                log_diff("mutes", &key_set(&self.muted), &key_set(&muted));
+                self.muted = muted;
+            }
+            Err(err) => warn!("Keeping current mutes, reload failed: {}", err),
+        }
+
+        match self.storage.ip_bans() {
+            Ok(ip_banned) => {
+                log_diff("IP bans", &key_set(&self.ip_banned), &key_set(&ip_banned));
+                self.ip_banned = ip_banned;
+            }
+            Err(err) => warn!("Keeping current IP bans, reload failed: {}", err),
+        }
+
+        banned
+    }
+
+    /// Checks that every file moderation depends on can be read.
+    pub fn check_files(&self) -> Result<()> {
+        for path in self.storage.watched_paths() {
+            File::open(path)?;
+        }
+        Ok(())
+    }
+
+    fn modification_times(&self) -> HashMap<PathBuf, SystemTime> {
+        self.storage
+            .watched_paths()
+            .into_iter()
+            .filter_map(|path| {
+                let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+                Some((path.to_path_buf(), modified))
+            })
+            .collect()
+    }
+
+    /// Returns the names of every role the user has.
+    pub fn roles_of<'a>(&'a self, user: &Uuid) -> impl Iterator<Item = &'a str> + 'a {
+        let legacy = self.moderators.contains(user).then_some(MODERATOR_ROLE);
+        let assigned = self.roles.get(user).into_iter().flatten();
+        legacy.into_iter().chain(assigned.map(String::as_str))
+    }
+
+    pub fn has_permission(&self, user: &Uuid, permission: Permission) -> bool {
+        self.roles_of(user).any(|role| {
+            self.config
+                .roles
+                .get(role)
+                .is_some_and(|permissions| permissions.contains(&permission))
+        })
+    }
+
+    /// Returns whether `issuer` may grant or revoke the role,
+    /// which requires holding every permission of it.
+    /// Actions without an issuer, like the ones of the admin API, may manage every role.
+    fn may_manage_role(&self, issuer: Option<&Uuid>, role: &str) -> bool {
+        let Some(issuer) = issuer else {
+            return true;
+        };
+        self.config.roles.get(role).is_none_or(|permissions| {
+            permissions
+                .iter()
+                .all(|permission| self.has_permission(issuer, *permission))
+        })
+    }
+
+    /// Assigns the role to the user on behalf of `issuer`.
+    pub fn grant_role(&mut self, issuer: Option<&Uuid>, user: &Uuid, role: &str) -> Result<()> {
+        if !self.config.roles.contains_key(role) {
+            return Err(ClientError::UnknownRole.into());
+        }
+        if !self.may_manage_role(issuer, role) {
+            return Err(ClientError::NotPermitted.into());
+        }
+
+        let mut roles = self.roles.get(user).cloned().unwrap_or_default();
+        if roles.insert(role.to_string()) {
+            self.storage.set_roles(user, &roles)?;
+            self.roles.insert(*user, roles);
+        }
+        Ok(())
+    }
+
+    /// Removes the role from the user on behalf of `issuer`.
+    /// Roles of users listed in the moderators file can't be revoked.
+    pub fn revoke_role(&mut self, issuer: Option<&Uuid>, user: &Uuid, role: &str) -> Result<()> {
+        if !self.may_manage_role(issuer, role) {
+            return Err(ClientError::NotPermitted.into());
+        }
+        let mut roles = self.roles.get(user).cloned().unwrap_or_default();
+        if !roles.remove(role) {
+            return Err(ClientError::RoleNotAssigned.into());
+        }
+
+        self.storage.set_roles(user, &roles)?;
+        if roles.is_empty() {
+            self.roles.remove(user);
+        } else {
+            self.roles.insert(*user, roles);
+        }
+        Ok(())
+    }
+
+    /// Ban user if user is not permitted to ban.
+    /// If `duration` is `None`, the ban is permanent.
+    /// Banning an already banned user replaces the previous ban.
+    pub fn ban(
+        &mut self,
+        user: &Uuid,
+        issuer: Option<Uuid>,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> Result<()> {
+        if self.has_permission(user, Permission::Ban) {
+            Err(ClientError::NotPermitted.into())
+        } else {
+            let ban = Sanction::new(*user, issuer, reason, duration);
+            self.storage.put_sanction(SanctionKind::Ban, &ban)?;
+            self.banned.insert(*user, ban);
             Ok(())
         }
     }
 
     pub fn unban(&mut self, user: &Uuid) -> Result<()> {
-        if self.banned.remove(user) {
-            let mut writer = BufWriter::new(File::create(&self.config.banned)?);
+        if self.banned.contains_key(user) {
+            self.storage.remove_sanctions(SanctionKind::Ban, &[*user])?;
+            self.banned.remove(user);
+            Ok(())
+        } else {
+            Err(ClientError::NotBanned.into())
+        }
+    }
 
-            for banned in &self.banned {
-                writeln!(writer, "{}", banned)?;
-            }
+    /// Returns the ban of the user if the user is currently banned.
+    pub fn get_ban(&self, user: &Uuid) -> Option<&Sanction> {
+        self.banned
+            .get(user)
+            .filter(|ban| !ban.is_expired(unix_time()))
+    }
+
+    /// Mute user if user is not permitted to mute.
+    /// If `duration` is `None`, the mute is permanent.
+    /// Muting an already muted user replaces the previous mute.
+    pub fn mute(
+        &mut self,
+        user: &Uuid,
+        issuer: Option<Uuid>,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> Result<()> {
+        if self.has_permission(user, Permission::Mute) {
+            Err(ClientError::NotPermitted.into())
+        } else {
+            let mute = Sanction::new(*user, issuer, reason, duration);
+            self.storage.put_sanction(SanctionKind::Mute, &mute)?;
+            self.muted.insert(*user, mute);
+            Ok(())
+        }
+    }
+
+    pub fn unmute(&mut self, user: &Uuid) -> Result<()> {
+        if self.muted.contains_key(user) {
+            self.storage
+                .remove_sanctions(SanctionKind::Mute, &[*user])?;
+            self.muted.remove(user);
+            Ok(())
+        } else {
+            Err(ClientError::NotMuted.into())
+        }
+    }
+
+    /// Returns the mute of the user if the user is currently muted.
+    pub fn get_mute(&self, user: &Uuid) -> Option<&Sanction> {
+        self.muted
+            .get(user)
+            .filter(|mute| !mute.is_expired(unix_time()))
+    }
+
+    /// Bans every connection from the range.
+    /// If `duration` is `None`, the ban is permanent.
+    /// Banning an already banned range replaces the previous ban.
+    pub fn ban_ip(
+        &mut self,
+        range: IpRange,
+        issuer: Option<Uuid>,
+        reason: Option<String>,
+        duration: Option<Duration>,
+    ) -> Result<()> {
+        let created = unix_time();
+        let ban = IpBan {
+            range,
+            issuer,
+            reason,
+            created,
+            until: duration.map(|duration| expiry(created, duration)),
+        };
+        self.storage.put_ip_ban(&ban)?;
+        self.ip_banned.insert(range, ban);
+        Ok(())
+    }
 
+    pub fn unban_ip(&mut self, range: IpRange) -> Result<()> {
+        if self.ip_banned.contains_key(&range) {
+            self.storage.remove_ip_bans(&[range])?;
+            self.ip_banned.remove(&range);
             Ok(())
         } else {
             Err(ClientError::NotBanned.into())
         }
     }
 
-    pub fn is_banned(&self, user: &Uuid) -> bool {
-        self.banned.contains(user)
+    /// Returns a ban of a range containing the address if there is one.
+    pub fn get_ip_ban(&self, ip: IpAddr) -> Option<&IpBan> {
+        let now = unix_time();
+        self.ip_banned
+            .values()
+            .find(|ban| ban.range.contains(ip) && !ban.is_expired(now))
+    }
+
+    /// Removes every expired ban and returns the users which were unbanned.
+    pub fn lift_expired_bans(&mut self) -> Result<Vec<Uuid>> {
+        lift_expired(self.storage.as_mut(), SanctionKind::Ban, &mut self.banned)
+    }
+
+    /// Removes every expired mute and returns the users which were unmuted.
+    pub fn lift_expired_mutes(&mut self) -> Result<Vec<Uuid>> {
+        lift_expired(self.storage.as_mut(), SanctionKind::Mute, &mut self.muted)
+    }
+
+    /// Removes every expired IP ban and returns the ranges which were unbanned.
+    pub fn lift_expired_ip_bans(&mut self) -> Result<Vec<IpRange>> {
+        let now = unix_time();
+        let expired: Vec<IpRange> = self
+            .ip_banned
+            .values()
+            .filter(|ban| ban.is_expired(now))
+            .map(|ban| ban.range)
+            .collect();
+
+        if !expired.is_empty() {
+            self.storage.remove_ip_bans(&expired)?;
+            for range in &expired {
+                self.ip_banned.remove(range);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Forgets the addresses which are older than the retention period.
+    /// Returns the amount of forgotten addresses.
+    pub fn prune_addresses(&mut self) -> Result<usize> {
+        let before = unix_time().saturating_sub(self.config.address_retention.as_secs());
+        self.storage.prune_addresses(before)
+    }
+
+    /// Returns the other users who have logged in from the addresses of `user`,
+    /// most recently seen first.
+    pub fn alts(&self, user: &Uuid) -> Result<Vec<Alt>> {
+        let ips: Vec<IpAddr> = self
+            .storage
+            .addresses_of(user)?
+            .into_iter()
+            .map(|record| record.ip)
+            .collect();
+
+        let mut alts: BTreeMap<Uuid, Alt> = BTreeMap::new();
+        for record in self.storage.users_from(&ips)? {
+            if record.user == *user {
+                continue;
+            }
+            let alt = alts.entry(record.user).or_insert_with(|| Alt {
+                uuid: record.user,
+                name: None,
+                addresses: Vec::new(),
+                last_seen: 0,
+                banned: self.get_ban(&record.user).is_some(),
+            });
+            alt.addresses.push(record.ip);
+            alt.last_seen = alt.last_seen.max(record.last_seen);
+        }
+
+        let mut alts: Vec<Alt> = alts.into_values().collect();
+        for alt in &mut alts {
+            alt.addresses.sort();
+            alt.name = self.storage.user(&alt.uuid)?.map(|record| record.name);
+        }
+        alts.sort_by_key(|alt| std::cmp::Reverse(alt.last_seen));
+        Ok(alts)
+    }
+
+    pub fn user(&self, uuid: &Uuid) -> Result<Option<UserRecord>> {
+        self.storage.user(uuid)
+    }
+
+    /// Returns the user who most recently logged in with this name, ignoring case.
+    pub fn user_by_name(&self, name: &str) -> Result<Option<UserRecord>> {
+        self.storage.user_by_name(name)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.storage.flush()
+    }
+
+    /// Remembers that the user has logged in, and from which address
+    /// unless addresses aren't retained.
+    pub fn record_login(&mut self, uuid: &Uuid, name: &str, ip: Option<IpAddr>) -> Result<()> {
+        let now = unix_time();
+        if let Some(ip) = ip
+            && !self.config.address_retention.is_zero()
+        {
+            self.storage.put_address(uuid, ip, now)?;
+        }
+        let first_seen = self
+            .storage
+            .user(uuid)?
+            .map_or(now, |record| record.first_seen);
+        self.storage.put_user(&UserRecord {
+            uuid: *uuid,
+            name: name.to_string(),
+            first_seen,
+            last_seen: now,
+        })
     }
 }
 
-fn read_ids(path: &Path) -> Result<HashSet<Uuid>> {
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
-            File::create(path)?;
-            return Ok(HashSet::new());
+fn lift_expired(
+    storage: &mut dyn Storage,
+    kind: SanctionKind,
+    sanctions: &mut HashMap<Uuid, Sanction>,
+) -> Result<Vec<Uuid>> {
+    let now = unix_time();
+    let expired: Vec<Uuid> = sanctions
+        .values()
+        .filter(|sanction| sanction.is_expired(now))
+        .map(|sanction| sanction.user)
+        .collect();
+
+    if !expired.is_empty() {
+        storage.remove_sanctions(kind, &expired)?;
+        for user in &expired {
+            sanctions.remove(user);
         }
-        Err(err) => return Err(err.into()),
-    };
-    let reader = BufReader::new(file);
-    let mut lines = HashSet::new();
-    for line in reader.lines() {
-        let line = line?;
-        if !line.is_empty() {
-            lines.insert(line.parse()?);
+    }
+
+    Ok(expired)
+}
+
+fn key_set<K: Copy + Eq + Hash, V>(entries: &HashMap<K, V>) -> HashSet<K> {
+    entries.keys().copied().collect()
+}
+
+/// Logs which entries were added to or removed from `what` and returns the added ones.
+fn log_diff<T: Copy + Eq + Hash + fmt::Display>(
+    what: &str,
+    old: &HashSet<T>,
+    new: &HashSet<T>,
+) -> Vec<T> {
+    for entry in old.difference(new) {
+        info!("Removed `{}` from {}.", entry, what);
+    }
+    new.difference(old)
+        .map(|entry| {
+            info!("Added `{}` to {}.", entry, what);
+            *entry
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens a moderation with file storage in an empty temporary directory.
+    fn moderation(name: &str, roles: BTreeMap<String, BTreeSet<Permission>>) -> Moderation {
+        let dir = std::env::temp_dir().join(format!(
+            "axochat-moderation-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = ModConfig {
+            database: dir.join("axochat.sqlite"),
+            moderators: dir.join("moderators.txt"),
+            role_assignments: dir.join("roles.json"),
+            users: dir.join("users.jsonl"),
+            addresses: dir.join("addresses.jsonl"),
+            audit_log: dir.join("audit.jsonl"),
+            banned: dir.join("banned.txt"),
+            muted: dir.join("muted.txt"),
+            ip_banned: dir.join("ip_banned.txt"),
+            roles,
+            ..ModConfig::default()
+        };
+        Moderation::new(config).unwrap()
+    }
+
+    fn is_not_permitted(res: Result<()>) -> bool {
+        matches!(
+            res,
+            Err(Error::AxoChat {
+                source: ClientError::NotPermitted
+            })
+        )
+    }
+
+    #[test]
+    fn roles_need_the_permissions_they_grant() {
+        let roles = BTreeMap::from([
+            (
+                String::from("admin"),
+                Permission::ALL.iter().copied().collect(),
+            ),
+            (
+                String::from("manager"),
+                BTreeSet::from([Permission::ManageRoles, Permission::Mute]),
+            ),
+            (String::from("helper"), BTreeSet::from([Permission::Mute])),
+        ]);
+        let mut moderation = moderation("roles", roles);
+        let (admin, manager, user) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        moderation.grant_role(None, &admin, "admin").unwrap();
+        moderation
+            .grant_role(Some(&admin), &manager, "manager")
+            .unwrap();
+
+        // Managers can't escalate their own or anyone else's privileges.
+        assert!(is_not_permitted(moderation.grant_role(
+            Some(&manager),
+            &manager,
+            "admin"
+        )));
+        assert!(is_not_permitted(moderation.grant_role(
+            Some(&manager),
+            &user,
+            "admin"
+        )));
+        assert!(!moderation.has_permission(&manager, Permission::Ban));
+        assert!(!moderation.has_permission(&user, Permission::Ban));
+
+        // Roles within their own permissions can be managed.
+        moderation
+            .grant_role(Some(&manager), &user, "helper")
+            .unwrap();
+        assert!(moderation.has_permission(&user, Permission::Mute));
+        moderation
+            .revoke_role(Some(&manager), &user, "helper")
+            .unwrap();
+
+        // Neither can stronger roles be revoked.
+        assert!(is_not_permitted(moderation.revoke_role(
+            Some(&manager),
+            &admin,
+            "admin"
+        )));
+        assert!(moderation.has_permission(&admin, Permission::Ban));
+        moderation
+            .revoke_role(Some(&admin), &manager, "manager")
+            .unwrap();
+    }
+
+    #[test]
+    fn compacts_login_records() {
+        let mut moderation = moderation("logins", BTreeMap::new());
+        let users = [Uuid::from_u128(1), Uuid::from_u128(2)];
+        let ips: [IpAddr; 2] = ["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+        for index in 0..1500 {
+            let user = &users[index % 2];
+            moderation
+                .record_login(user, "Notch", Some(ips[index % 2]))
+                .unwrap();
         }
+
+        let line_count = |path: &PathBuf| fs::read_to_string(path).unwrap().lines().count();
+        assert_eq!(line_count(&moderation.config.users), 502);
+        assert_eq!(line_count(&moderation.config.addresses), 502);
+        assert!(moderation.user(&users[1]).unwrap().is_some());
     }
-    Ok(lines)
 }
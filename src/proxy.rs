@@ -0,0 +1,517 @@
+use crate::config::ForwardedHeader;
+use crate::ip::IpRange;
+use log::*;
+
+use actix_http::{
+    HttpService, Protocol, Request, Response, body::MessageBody, error::DispatchError,
+};
+use actix_rt::net::TcpStream;
+use actix_server::Server;
+use actix_service::{
+    IntoServiceFactory, Service, ServiceFactory, ServiceFactoryExt, fn_service, map_config,
+};
+use actix_web::{HttpRequest, dev::AppConfig};
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The first bytes of a PROXY protocol v2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// The maximum length of a PROXY protocol v1 header, including the line break.
+const V1_MAX_LENGTH: usize = 107;
+/// The time a proxy has to send the PROXY protocol header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the address of the client which sent the request.
+///
+/// If the peer is a trusted proxy, the client is taken from the `header`.
+/// Its entries are followed from the right as long as they are trusted proxies themselves.
+/// Other headers are ignored, as proxies usually pass them through from the client.
+pub fn client_ip(
+    req: &HttpRequest,
+    trusted_proxies: &[IpRange],
+    header: ForwardedHeader,
+) -> Option<IpAddr> {
+    let mut client = req.peer_addr()?.ip().to_canonical();
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|range| range.contains(ip));
+    if !is_trusted(client) {
+        return Some(client);
+    }
+
+    let chain = match header {
+        ForwardedHeader::XForwardedFor => x_forwarded_for(req),
+        ForwardedHeader::Forwarded => forwarded_for(req),
+        ForwardedHeader::None => Vec::new(),
+    };
+    for entry in chain.into_iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match entry {
+            Some(ip) => client = ip.to_canonical(),
+            // Obfuscated and unknown entries hide everything before them.
+            None => break,
+        }
+    }
+    Some(client)
+}
+
+/// Returns the `for` parameters of every `Forwarded` header.
+fn forwarded_for(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    req.headers()
+        .get_all("forwarded")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value.trim().trim_matches('"')))
+            })
+        })
+        .collect()
+}
+
+/// Parses a node like `192.0.2.60`, `192.0.2.60:4711` or `[2001:db8::17]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            let (ip, _port) = node.rsplit_once(':')?;
+            ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+        })
+}
+
+fn x_forwarded_for(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    req.headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Starts an HTTP server at `address` which expects every connection to begin
+/// with a PROXY protocol header.
+/// The source address in the header is used as peer address of the requests.
+/// Connections from peers which aren't trusted proxies are closed.
+pub fn bind<F, I, S, B>(
+    address: SocketAddr,
+    trusted_proxies: Vec<IpRange>,
+    factory: F,
+) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    Ok(Server::build()
+        .disable_signals()
+        .bind("axochat-proxy-protocol", address, move || {
+            let trusted_proxies = trusted_proxies.clone();
+            let app = factory()
+                .into_factory()
+                .map_err(|err| err.into().error_response());
+
+            fn_service(move |stream: TcpStream| {
+                let trusted_proxies = trusted_proxies.clone();
+                async move {
+                    accept(stream, &trusted_proxies).await.map_err(|err| {
+                        warn!("Rejected PROXY protocol connection: {}", err);
+                        DispatchError::Io(err)
+                    })
+                }
+            })
+            .and_then(HttpService::build().finish(map_config(app, |_| AppConfig::default())))
+        })?
+        .run())
+}
+
+async fn accept(
+    mut stream: TcpStream,
+    trusted_proxies: &[IpRange],
+) -> io::Result<(TcpStream, Protocol, Option<SocketAddr>)> {
+    let peer = stream.peer_addr()?;
+    if !trusted_proxies
+        .iter()
+        .any(|range| range.contains(peer.ip()))
+    {
+        return Err(invalid_data(format!("{} is not a trusted proxy", peer)));
+    }
+
+    let source = actix_rt::time::timeout(HEADER_TIMEOUT, read_header(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no PROXY protocol header"))??;
+    // Health checks of the proxy itself don't carry a source address.
+    Ok((stream, Protocol::Http1, Some(source.unwrap_or(peer))))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads a PROXY protocol v1 or v2 header without reading anything after it.
+/// Returns the source address, unless the proxy didn't forward a TCP connection.
+async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut start = [0; 5];
+    stream.read_exact(&mut start).await?;
+    if &start == b"PROXY" {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..5] {
+        read_v2(stream).await
+    } else {
+        Err(invalid_data("missing PROXY protocol header"))
+    }
+}
+
+/// Reads the rest of a line like `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`.
+async fn read_v1<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut line = b"PROXY".to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid_data("PROXY protocol v1 header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid_data("PROXY protocol v1 header is not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "TCP4" | "TCP6", source, _destination, port, _] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .map_err(|_| invalid_data("invalid source address in PROXY protocol header"))?;
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| invalid_data("invalid source port in PROXY protocol header"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        _ => Err(invalid_data("malformed PROXY protocol v1 header")),
+    }
+}
+
+/// Reads the rest of a binary header after the first five bytes of the signature.
+async fn read_v2<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut header = [0; 11];
+    stream.read_exact(&mut header).await?;
+    if header[..7] != V2_SIGNATURE[5..] {
+        return Err(invalid_data("invalid PROXY protocol v2 signature"));
+    }
+    let version_command = header[7];
+    let family = header[8];
+    let length = u16::from_be_bytes([header[9], header[10]]) as usize;
+    if version_command >> 4 != 2 {
+        return Err(invalid_data("unsupported PROXY protocol version"));
+    }
+
+    let mut addresses = vec![0; length];
+    stream.read_exact(&mut addresses).await?;
+
+    // LOCAL connections are initiated by the proxy itself.
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+    let source = match family >> 4 {
+        // AF_INET
+        0x1 if length >= 12 => {
+            let ip = <[u8; 4]>::try_from(&addresses[..4]).expect("slice has length 4");
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        // AF_INET6
+        0x2 if length >= 36 => {
+            let ip = <[u8; 16]>::try_from(&addresses[..16]).expect("slice has length 16");
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::from(ip), port))
+        }
+        0x1 | 0x2 => return Err(invalid_data("truncated PROXY protocol v2 addresses")),
+        _ => None,
+    };
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn trusted() -> Vec<IpRange> {
+        vec!["127.0.0.1".parse().unwrap(), "10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn resolve(peer: &str, header: ForwardedHeader, headers: &[(&str, &str)]) -> IpAddr {
+        let mut req = TestRequest::default().peer_addr(peer.parse().unwrap());
+        for &(name, value) in headers {
+            req = req.append_header((name, value));
+        }
+        client_ip(&req.to_http_request(), &trusted(), header).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn follows_trusted_proxies() {
+        let xff = ForwardedHeader::XForwardedFor;
+        let peer = "127.0.0.1:1234";
+        assert_eq!(
+            resolve(peer, xff, &[("x-forwarded-for", "192.0.2.1, 10.0.0.2")]),
+            ip("192.0.2.1")
+        );
+        // Entries left of the first untrusted address may be forged by the client.
+        assert_eq!(
+            resolve(
+                peer,
+                xff,
+                &[("x-forwarded-for", "203.0.113.9, 192.0.2.1, 10.0.0.2")]
+            ),
+            ip("192.0.2.1")
+        );
+        // Only trusted proxies are trusted in the chain.
+        assert_eq!(
+            resolve(peer, xff, &[("x-forwarded-for", "10.0.0.3,10.0.0.2")]),
+            ip("10.0.0.3")
+        );
+        // Headers of clients connecting directly are ignored.
+        assert_eq!(
+            resolve(
+                "198.51.100.7:1234",
+                xff,
+                &[("x-forwarded-for", "192.0.2.1")]
+            ),
+            ip("198.51.100.7")
+        );
+        assert_eq!(resolve(peer, xff, &[]), ip("127.0.0.1"));
+        assert_eq!(
+            resolve(
+                "[::ffff:127.0.0.1]:1234",
+                xff,
+                &[("x-forwarded-for", "::ffff:192.0.2.1")]
+            ),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn joins_header_lines() {
+        assert_eq!(
+            resolve(
+                "127.0.0.1:1234",
+                ForwardedHeader::XForwardedFor,
+                &[
+                    ("x-forwarded-for", "203.0.113.9, 192.0.2.1"),
+                    ("x-forwarded-for", "10.0.0.2"),
+                ]
+            ),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            resolve(
+                "127.0.0.1:1234",
+                ForwardedHeader::Forwarded,
+                &[
+                    ("forwarded", "for=203.0.113.9, for=192.0.2.1"),
+                    ("forwarded", "for=10.0.0.2;proto=https"),
+                ]
+            ),
+            ip("192.0.2.1")
+        );
+    }
+
+    #[test]
+    fn reads_forwarded_nodes() {
+        let forwarded = ForwardedHeader::Forwarded;
+        let peer = "127.0.0.1:1234";
+        assert_eq!(
+            resolve(
+                peer,
+                forwarded,
+                &[("forwarded", r#"for="[2001:db8::1]:4711";proto=https"#)]
+            ),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            resolve(
+                peer,
+                forwarded,
+                &[("forwarded", "by=10.0.0.1;For=192.0.2.60:4711")]
+            ),
+            ip("192.0.2.60")
+        );
+        // Obfuscated and unknown nodes hide the client, so the last proxy is used.
+        assert_eq!(
+            resolve(
+                peer,
+                forwarded,
+                &[("forwarded", "for=192.0.2.1, for=_hidden, for=10.0.0.2")]
+            ),
+            ip("10.0.0.2")
+        );
+        assert_eq!(
+            resolve(peer, forwarded, &[("forwarded", "for=unknown")]),
+            ip("127.0.0.1")
+        );
+    }
+
+    #[test]
+    fn reads_only_the_configured_header() {
+        let headers = [
+            ("forwarded", "for=203.0.113.9"),
+            ("x-forwarded-for", "192.0.2.1"),
+        ];
+        let peer = "127.0.0.1:1234";
+        assert_eq!(
+            resolve(peer, ForwardedHeader::XForwardedFor, &headers),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            resolve(peer, ForwardedHeader::Forwarded, &headers),
+            ip("203.0.113.9")
+        );
+        assert_eq!(
+            resolve(peer, ForwardedHeader::None, &headers),
+            ip("127.0.0.1")
+        );
+        // A missing header doesn't fall back to the other one.
+        assert_eq!(
+            resolve(
+                peer,
+                ForwardedHeader::Forwarded,
+                &[("x-forwarded-for", "192.0.2.1")]
+            ),
+            ip("127.0.0.1")
+        );
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node("192.0.2.60"), Some(ip("192.0.2.60")));
+        assert_eq!(parse_node("192.0.2.60:4711"), Some(ip("192.0.2.60")));
+        assert_eq!(parse_node("2001:db8::17"), Some(ip("2001:db8::17")));
+        assert_eq!(parse_node("[2001:db8::17]"), Some(ip("2001:db8::17")));
+        assert_eq!(parse_node("[2001:db8::17]:4711"), Some(ip("2001:db8::17")));
+        assert_eq!(parse_node("[2001:db8::17"), None);
+        assert_eq!(parse_node("[192.0.2.60]"), None);
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node(""), None);
+    }
+
+    async fn read(header: &[u8]) -> io::Result<Option<SocketAddr>> {
+        let mut stream = header;
+        read_header(&mut stream).await
+    }
+
+    #[actix_rt::test]
+    async fn reads_v1_headers() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET /")
+                .await
+                .unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n")
+                .await
+                .unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+
+        let malformed: &[&[u8]] = &[
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443 1\r\n",
+            b"PROXY TCP4  192.0.2.1 192.0.2.2 56324 443\r\n",
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.300 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 -1 443\r\n",
+            b"PROXY TCP4 \xff 192.0.2.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\n",
+            b"PROXY",
+            b"PROX",
+            b"GET / HTTP/1.1\r\n",
+        ];
+        for header in malformed {
+            assert!(
+                read(header).await.is_err(),
+                "{:?}",
+                String::from_utf8_lossy(header)
+            );
+        }
+
+        let long = [&b"PROXY UNKNOWN "[..], &[b'a'; 100], b"\r\n"].concat();
+        assert!(read(&long).await.is_err());
+    }
+
+    fn v2(command: u8, family: u8, length: u16, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&length.to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[actix_rt::test]
+    async fn reads_v2_headers() {
+        let inet = [192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(
+            read(&v2(1, 0x11, 12, &inet)).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+
+        let source: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut inet6 = source.octets().to_vec();
+        inet6.extend_from_slice(&[0; 16]);
+        inet6.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        assert_eq!(
+            read(&v2(1, 0x21, 36, &inet6)).await.unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        // TLVs after the addresses are skipped.
+        let with_tlv = [&inet[..], &[0x04, 0x00, 0x01, 0x00]].concat();
+        assert_eq!(
+            read(&v2(1, 0x11, 16, &with_tlv)).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        // LOCAL connections and unknown families don't carry a client address.
+        assert_eq!(read(&v2(0, 0x11, 12, &inet)).await.unwrap(), None);
+        assert_eq!(read(&v2(0, 0x00, 0, &[])).await.unwrap(), None);
+        assert_eq!(read(&v2(1, 0x31, 0, &[])).await.unwrap(), None);
+
+        // Lengths too short for the family.
+        assert!(read(&v2(1, 0x11, 4, &inet[..4])).await.is_err());
+        assert!(read(&v2(1, 0x21, 12, &inet)).await.is_err());
+        // Lengths beyond the data which was sent.
+        assert!(read(&v2(1, 0x11, 12, &inet[..6])).await.is_err());
+        assert!(read(&v2(0, 0x00, 8, &[])).await.is_err());
+        // Wrong versions, signatures and truncated headers.
+        let mut wrong_version = v2(1, 0x11, 12, &inet);
+        wrong_version[12] = 0x11;
+        assert!(read(&wrong_version).await.is_err());
+        let mut wrong_signature = v2(1, 0x11, 12, &inet);
+        wrong_signature[8] = b'X';
+        assert!(read(&wrong_signature).await.is_err());
+        assert!(read(&V2_SIGNATURE[..9]).await.is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::*;
+use log::*;
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Returns `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".tmp")
+}
+
+/// Replaces the contents of the file at `path` without ever leaving it partially written.
+/// The contents are written to a temporary file, synced to disk and renamed to `path`.
+pub fn write(path: &Path, contents: &[u8]) -> Result<()> {
+    let temp = temp_path(path);
+    {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp, path)?;
+    sync_parent(path);
+    Ok(())
+}
+
+/// Makes sure the rename is persisted.
+/// Not every platform supports syncing directories, so errors are only logged.
+fn sync_parent(path: &Path) {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Err(err) = File::open(parent).and_then(|dir| dir.sync_all()) {
+        debug!("Could not sync directory {:?}: {}", parent, err);
+    }
+}
+
+/// Removes the temporary file of an interrupted `write`.
+/// As the rename is the last step, the file at `path` still has its previous contents.
+pub fn recover(path: &Path) -> Result<()> {
+    let temp = temp_path(path);
+    match fs::remove_file(&temp) {
+        Ok(()) => {
+            warn!(
+                "Removed stale temporary file {:?}; the last write to {:?} was interrupted.",
+                temp, path
+            );
+            Ok(())
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// An exclusive lock on a file, held until dropped.
+/// It prevents multiple server instances from writing the same file.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Locks the file at `path` by locking `<path>.lock`.
+    pub fn acquire(path: &Path) -> Result<FileLock> {
+        let lock_path = with_suffix(path, ".lock");
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(FileLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(Error::Locked { path: lock_path }),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use super::{
+    AddressRecord, SanctionKind, Storage, UserRecord,
+    atomic::{self, FileLock},
+};
+use crate::config::ModConfig;
+use crate::error::*;
+use crate::ip::IpRange;
+use crate::moderation::{IpBan, Sanction};
+use log::*;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// The users and addresses files are only compacted once they have at least this many lines.
+const MIN_COMPACT_LINES: usize = 1000;
+
+/// Stores everything in plain files:
+/// sanctions, users and addresses as JSON lines, role assignments as a JSON object
+/// and moderators as one uuid per line.
+///
+/// Files are replaced atomically and locked while the storage is open.
+/// Only logins are appended to the users and addresses files, which are compacted at startup
+/// and once they have grown to twice the records in them.
+pub struct FileStorage {
+    moderators: PathBuf,
+    banned: PathBuf,
+    muted: PathBuf,
+    ip_banned: PathBuf,
+    role_assignments: PathBuf,
+    users_path: PathBuf,
+    users: HashMap<Uuid, UserRecord>,
+    /// The amount of lines in the users file.
+    users_lines: usize,
+    addresses_path: PathBuf,
+    addresses: HashMap<(Uuid, IpAddr), AddressRecord>,
+    /// The amount of lines in the addresses file.
+    addresses_lines: usize,
+    _locks: Vec<FileLock>,
+}
+
+impl FileStorage {
+    pub fn new(config: &ModConfig) -> Result<FileStorage> {
+        let paths = [
+            &config.moderators,
+            &config.banned,
+            &config.muted,
+            &config.ip_banned,
+            &config.role_assignments,
+            &config.users,
+            &config.addresses,
+        ];
+        let mut locks = Vec::with_capacity(paths.len());
+        for path in paths {
+            locks.push(FileLock::acquire(path)?);
+            atomic::recover(path)?;
+        }
+
+        let users = read_users(&config.users)?;
+        // Compact the file, as every login appends a line.
+        write_lines(&config.users, users.values())?;
+        let addresses = read_addresses(&config.addresses)?;
+        write_lines(&config.addresses, addresses.values())?;
+
+        Ok(FileStorage {
+            moderators: config.moderators.clone(),
+            banned: config.banned.clone(),
+            muted: config.muted.clone(),
+            ip_banned: config.ip_banned.clone(),
+            role_assignments: config.role_assignments.clone(),
+            users_path: config.users.clone(),
+            users_lines: users.len(),
+            users,
+            addresses_path: config.addresses.clone(),
+            addresses_lines: addresses.len(),
+            addresses,
+            _locks: locks,
+        })
+    }
+
+    fn sanctions_path(&self, kind: SanctionKind) -> &Path {
+        match kind {
+            SanctionKind::Ban => &self.banned,
+            SanctionKind::Mute => &self.muted,
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn sanctions(&self, kind: SanctionKind) -> Result<HashMap<Uuid, Sanction>> {
+        read_sanctions(self.sanctions_path(kind))
+    }
+
+    fn put_sanction(&mut self, kind: SanctionKind, sanction: &Sanction) -> Result<()> {
+        let path = self.sanctions_path(kind);
+        let mut sanctions = read_sanctions(path)?;
+        sanctions.insert(sanction.user, sanction.clone());
+        write_lines(path, sanctions.values())
+    }
+
+    fn remove_sanctions(&mut self, kind: SanctionKind, users: &[Uuid]) -> Result<()> {
+        let path = self.sanctions_path(kind);
+        let mut sanctions = read_sanctions(path)?;
+        for user in users {
+            sanctions.remove(user);
+        }
+        write_lines(path, sanctions.values())
+    }
+
+    fn ip_bans(&self) -> Result<HashMap<IpRange, IpBan>> {
+        read_ip_bans(&self.ip_banned)
+    }
+
+    fn put_ip_ban(&mut self, ban: &IpBan) -> Result<()> {
+        let mut bans = read_ip_bans(&self.ip_banned)?;
+        bans.insert(ban.range, ban.clone());
+        write_lines(&self.ip_banned, bans.values())
+    }
+
+    fn remove_ip_bans(&mut self, ranges: &[IpRange]) -> Result<()> {
+        let mut bans = read_ip_bans(&self.ip_banned)?;
+        for range in ranges {
+            bans.remove(range);
+        }
+        write_lines(&self.ip_banned, bans.values())
+    }
+
+    fn moderators(&self) -> Result<HashSet<Uuid>> {
+        read_ids(&self.moderators)
+    }
+
+    fn role_assignments(&self) -> Result<HashMap<Uuid, BTreeSet<String>>> {
+        read_roles(&self.role_assignments)
+    }
+
+    fn set_roles(&mut self, user: &Uuid, roles: &BTreeSet<String>) -> Result<()> {
+        let mut assignments = read_roles(&self.role_assignments)?;
+        if roles.is_empty() {
+            assignments.remove(user);
+        } else {
+            assignments.insert(*user, roles.clone());
+        }
+        write_roles(&self.role_assignments, &assignments)
+    }
+
+    fn user(&self, uuid: &Uuid) -> Result<Option<UserRecord>> {
+        Ok(self.users.get(uuid).cloned())
+    }
+
+    fn user_by_name(&self, name: &str) -> Result<Option<UserRecord>> {
+        Ok(self
+            .users
+            .values()
+            .filter(|user| user.name.eq_ignore_ascii_case(name))
+            .max_by_key(|user| user.last_seen)
+            .cloned())
+    }
+
+    fn put_user(&mut self, user: &UserRecord) -> Result<()> {
+        append_line(&self.users_path, user)?;
+        self.users.insert(user.uuid, user.clone());
+        self.users_lines += 1;
+        compact_if_grown(&self.users_path, &mut self.users_lines, self.users.values())
+    }
+
+    fn put_address(&mut self, user: &Uuid, ip: IpAddr, time: u64) -> Result<()> {
+        let first_seen = self
+            .addresses
+            .get(&(*user, ip))
+            .map_or(time, |record| record.first_seen);
+        let record = AddressRecord {
+            user: *user,
+            ip,
+            first_seen,
+            last_seen: time,
+        };
+        append_line(&self.addresses_path, &record)?;
+        self.addresses.insert((*user, ip), record);
+        self.addresses_lines += 1;
+        compact_if_grown(
+            &self.addresses_path,
+            &mut self.addresses_lines,
+            self.addresses.values(),
+        )
+    }
+
+    fn addresses_of(&self, user: &Uuid) -> Result<Vec<AddressRecord>> {
+        Ok(self
+            .addresses
+            .values()
+            .filter(|record| record.user == *user)
+            .cloned()
+            .collect())
+    }
+
+    fn users_from(&self, ips: &[IpAddr]) -> Result<Vec<AddressRecord>> {
+        Ok(self
+            .addresses
+            .values()
+            .filter(|record| ips.contains(&record.ip))
+            .cloned()
+            .collect())
+    }
+
+    fn prune_addresses(&mut self, before: u64) -> Result<usize> {
+        let count = self.addresses.len();
+        self.addresses
+            .retain(|_, record| record.last_seen >= before);
+        let removed = count - self.addresses.len();
+        if removed > 0 {
+            write_lines(&self.addresses_path, self.addresses.values())?;
+            self.addresses_lines = self.addresses.len();
+        }
+        Ok(removed)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // Every write is synced immediately.
+        Ok(())
+    }
+
+    fn watched_paths(&self) -> Vec<&Path> {
+        vec![
+            &self.moderators,
+            &self.banned,
+            &self.muted,
+            &self.ip_banned,
+            &self.role_assignments,
+        ]
+    }
+}
+
+/// Reads sanctions stored as JSON lines.
+/// Lines only containing a uuid are read as permanent sanctions without a reason.
+fn read_sanctions(path: &Path) -> Result<HashMap<Uuid, Sanction>> {
+    let mut sanctions = HashMap::new();
+    for (index, line) in read_lines(path)? {
+        let sanction = if line.starts_with('{') {
+            serde_json::from_str::<Sanction>(&line).map_err(|err| err.to_string())
+        } else {
+            line.parse()
+                .map(|user| Sanction {
+                    user,
+                    issuer: None,
+                    reason: None,
+                    created: 0,
+                    until: None,
+                })
+                .map_err(|err: uuid::Error| err.to_string())
+        }
+        .map_err(|message| Error::Malformed {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        })?;
+        sanctions.insert(sanction.user, sanction);
+    }
+    Ok(sanctions)
+}
+
+/// Reads IP bans stored as JSON lines.
+/// Lines only containing an address or a CIDR block are read as permanent bans without a reason.
+fn read_ip_bans(path: &Path) -> Result<HashMap<IpRange, IpBan>> {
+    let mut bans = HashMap::new();
+    for (index, line) in read_lines(path)? {
+        let ban = if line.starts_with('{') {
+            serde_json::from_str::<IpBan>(&line).map_err(|err| err.to_string())
+        } else {
+            line.parse().map(|range| IpBan {
+                range,
+                issuer: None,
+                reason: None,
+                created: 0,
+                until: None,
+            })
+        }
+        .map_err(|message| Error::Malformed {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        })?;
+        bans.insert(ban.range, ban);
+    }
+    Ok(bans)
+}
+
+fn read_users(path: &Path) -> Result<HashMap<Uuid, UserRecord>> {
+    let mut users = HashMap::new();
+    for (_, line) in read_lines(path)? {
+        match serde_json::from_str::<UserRecord>(&line) {
+            Ok(user) => {
+                users.insert(user.uuid, user);
+            }
+            Err(err) => warn!("Skipping malformed user record in {:?}: {}", path, err),
+        }
+    }
+    Ok(users)
+}
+
+fn read_addresses(path: &Path) -> Result<HashMap<(Uuid, IpAddr), AddressRecord>> {
+    let mut addresses = HashMap::new();
+    for (_, line) in read_lines(path)? {
+        match serde_json::from_str::<AddressRecord>(&line) {
+            Ok(record) => {
+                addresses.insert((record.user, record.ip), record);
+            }
+            Err(err) => warn!("Skipping malformed address record in {:?}: {}", path, err),
+        }
+    }
+    Ok(addresses)
+}
+
+/// Reads a file containing one uuid per line.
+pub(super) fn read_ids(path: &Path) -> Result<HashSet<Uuid>> {
+    let mut ids = HashSet::new();
+    for (index, line) in read_lines(path)? {
+        let id = line.parse().map_err(|err: uuid::Error| Error::Malformed {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message: err.to_string(),
+        })?;
+        ids.insert(id);
+    }
+    Ok(ids)
+}
+
+/// Returns the trimmed, non-empty lines of the file with their index.
+/// If the file does not exist, an empty one is created.
+fn read_lines(path: &Path) -> Result<Vec<(usize, String)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            File::create(path)?;
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut lines = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            lines.push((index, line.to_string()));
+        }
+    }
+    Ok(lines)
+}
+
+fn append_line<T: serde::Serialize>(path: &Path, value: &T) -> Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(value)?)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn write_lines<'a, T: serde::Serialize + 'a>(
+    path: &Path,
+    values: impl IntoIterator<Item = &'a T>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    for value in values {
+        serde_json::to_writer(&mut buf, value)?;
+        buf.push(b'\n');
+    }
+
+    atomic::write(path, &buf)
+}
+
+/// Rewrites a file of appended records with only the current `records`
+/// once it has grown to twice their amount.
+fn compact_if_grown<'a, T: serde::Serialize + 'a>(
+    path: &Path,
+    lines: &mut usize,
+    records: impl ExactSizeIterator<Item = &'a T>,
+) -> Result<()> {
+    let count = records.len();
+    if *lines < (2 * count).max(MIN_COMPACT_LINES) {
+        return Ok(());
+    }
+    write_lines(path, records)?;
+    debug!("Compacted {:?} from {} to {} lines.", path, lines, count);
+    *lines = count;
+    Ok(())
+}
+
+/// Reads the role assignments stored as a JSON object mapping uuids to role names.
+fn read_roles(path: &Path) -> Result<HashMap<Uuid, BTreeSet<String>>> {
+    match fs::read_to_string(path) {
+        Ok(input) if input.trim().is_empty() => Ok(HashMap::new()),
+        Ok(input) => serde_json::from_str(&input).map_err(|err| Error::Malformed {
+            path: path.to_path_buf(),
+            line: err.line(),
+            message: err.to_string(),
+        }),
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let roles = HashMap::new();
+            write_roles(path, &roles)?;
+            Ok(roles)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_roles(path: &Path, roles: &HashMap<Uuid, BTreeSet<String>>) -> Result<()> {
+    atomic::write(path, &serde_json::to_vec_pretty(roles)?)
+}
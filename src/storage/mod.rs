@@ -0,0 +1,113 @@
+pub mod atomic;
+mod file;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use file::FileStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+use crate::config::{ModConfig, StorageBackend};
+use crate::error::*;
+use crate::ip::IpRange;
+use crate::moderation::{IpBan, Sanction};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{net::IpAddr, path::Path};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanctionKind {
+    Ban,
+    Mute,
+}
+
+/// A user who has logged in at least once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub uuid: Uuid,
+    pub name: String,
+    /// The unix timestamp in seconds of the first login.
+    pub first_seen: u64,
+    /// The unix timestamp in seconds of the last login.
+    pub last_seen: u64,
+}
+
+/// An IP address a user has logged in from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub user: Uuid,
+    pub ip: IpAddr,
+    /// The unix timestamp in seconds of the first login from this address.
+    pub first_seen: u64,
+    /// The unix timestamp in seconds of the last login from this address.
+    pub last_seen: u64,
+}
+
+/// Persists the moderation state and the known users.
+pub trait Storage {
+    /// Returns every stored sanction of this kind, including expired ones.
+    fn sanctions(&self, kind: SanctionKind) -> Result<HashMap<Uuid, Sanction>>;
+
+    /// Stores the sanction, replacing a previous sanction of the same kind and user.
+    fn put_sanction(&mut self, kind: SanctionKind, sanction: &Sanction) -> Result<()>;
+
+    fn remove_sanctions(&mut self, kind: SanctionKind, users: &[Uuid]) -> Result<()>;
+
+    /// Returns every stored IP ban, including expired ones.
+    fn ip_bans(&self) -> Result<HashMap<IpRange, IpBan>>;
+
+    /// Stores the IP ban, replacing a previous ban of the same range.
+    fn put_ip_ban(&mut self, ban: &IpBan) -> Result<()>;
+
+    fn remove_ip_bans(&mut self, ranges: &[IpRange]) -> Result<()>;
+
+    /// Returns the users listed as moderators, who have the `moderator` role
+    /// in addition to their role assignments.
+    fn moderators(&self) -> Result<HashSet<Uuid>>;
+
+    fn role_assignments(&self) -> Result<HashMap<Uuid, BTreeSet<String>>>;
+
+    /// Replaces the roles of the user.
+    fn set_roles(&mut self, user: &Uuid, roles: &BTreeSet<String>) -> Result<()>;
+
+    fn user(&self, uuid: &Uuid) -> Result<Option<UserRecord>>;
+
+    /// Returns the user who most recently logged in with this name, ignoring case.
+    fn user_by_name(&self, name: &str) -> Result<Option<UserRecord>>;
+
+    fn put_user(&mut self, user: &UserRecord) -> Result<()>;
+
+    /// Records that the user logged in from `ip` at the unix timestamp `time`.
+    fn put_address(&mut self, user: &Uuid, ip: IpAddr, time: u64) -> Result<()>;
+
+    /// Returns every address the user has logged in from.
+    fn addresses_of(&self, user: &Uuid) -> Result<Vec<AddressRecord>>;
+
+    /// Returns the records of every user who has logged in from one of `ips`.
+    fn users_from(&self, ips: &[IpAddr]) -> Result<Vec<AddressRecord>>;
+
+    /// Forgets the addresses which weren't used since the unix timestamp `before`.
+    /// Returns the amount of removed records.
+    fn prune_addresses(&mut self, before: u64) -> Result<usize>;
+
+    /// Makes sure everything which was stored is on disk.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Returns the files which may be edited while the server is running.
+    /// The moderators, sanctions, IP bans and role assignments are reloaded
+    /// once one of them changes.
+    fn watched_paths(&self) -> Vec<&Path>;
+}
+
+/// Opens the storage backend selected in the configuration.
+pub fn open(config: &ModConfig) -> Result<Box<dyn Storage>> {
+    Ok(match config.storage {
+        StorageBackend::File => Box::new(FileStorage::new(config)?),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            Box::new(SqliteStorage::new(&config.database, &config.moderators)?)
+        }
+    })
+}
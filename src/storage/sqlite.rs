@@ -0,0 +1,366 @@
+use super::{AddressRecord, SanctionKind, Storage, UserRecord, file};
+use crate::error::*;
+use crate::ip::IpRange;
+use crate::moderation::{IpBan, Sanction};
+use log::*;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS moderators (
+    user TEXT PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS sanctions (
+    kind TEXT NOT NULL,
+    user TEXT NOT NULL,
+    issuer TEXT,
+    reason TEXT,
+    created INTEGER NOT NULL,
+    until INTEGER,
+    PRIMARY KEY (kind, user)
+);
+CREATE TABLE IF NOT EXISTS ip_bans (
+    cidr TEXT PRIMARY KEY,
+    issuer TEXT,
+    reason TEXT,
+    created INTEGER NOT NULL,
+    until INTEGER
+);
+CREATE TABLE IF NOT EXISTS roles (
+    user TEXT NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY (user, role)
+);
+CREATE TABLE IF NOT EXISTS users (
+    uuid TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS addresses (
+    user TEXT NOT NULL,
+    ip TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    PRIMARY KEY (user, ip)
+);
+CREATE INDEX IF NOT EXISTS addresses_by_ip ON addresses (ip);
+";
+
+/// Stores everything in an SQLite database.
+pub struct SqliteStorage {
+    path: PathBuf,
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`.
+    /// When the database is created, the moderators are imported from the `legacy_moderators` file.
+    pub fn new(path: &Path, legacy_moderators: &Path) -> Result<SqliteStorage> {
+        let connection = Connection::open(path)?;
+        let has_moderators: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'moderators')",
+            [],
+            |row| row.get(0),
+        )?;
+        connection.execute_batch(SCHEMA)?;
+
+        let storage = SqliteStorage {
+            path: path.to_path_buf(),
+            connection,
+        };
+        if !has_moderators && legacy_moderators.exists() {
+            let moderators = file::read_ids(legacy_moderators)?;
+            for moderator in &moderators {
+                storage.connection.execute(
+                    "INSERT OR IGNORE INTO moderators (user) VALUES (?1)",
+                    params![moderator.to_string()],
+                )?;
+            }
+            info!(
+                "Imported {} moderators from {:?} into the database.",
+                moderators.len(),
+                legacy_moderators
+            );
+        }
+        Ok(storage)
+    }
+}
+
+fn kind_name(kind: SanctionKind) -> &'static str {
+    match kind {
+        SanctionKind::Ban => "ban",
+        SanctionKind::Mute => "mute",
+    }
+}
+
+fn parse_uuid(index: usize, value: String) -> rusqlite::Result<Uuid> {
+    value.parse().map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+/// Parses the text in column `index` with `FromStr`.
+fn parse_text<T>(index: usize, value: String) -> rusqlite::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|err: T::Err| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            rusqlite::types::Type::Text,
+            err.to_string().into(),
+        )
+    })
+}
+
+fn read_address(row: &rusqlite::Row) -> rusqlite::Result<AddressRecord> {
+    Ok(AddressRecord {
+        user: parse_uuid(0, row.get(0)?)?,
+        ip: parse_text(1, row.get(1)?)?,
+        first_seen: row.get(2)?,
+        last_seen: row.get(3)?,
+    })
+}
+
+fn read_user(row: &rusqlite::Row) -> rusqlite::Result<UserRecord> {
+    Ok(UserRecord {
+        uuid: parse_uuid(0, row.get(0)?)?,
+        name: row.get(1)?,
+        first_seen: row.get(2)?,
+        last_seen: row.get(3)?,
+    })
+}
+
+impl Storage for SqliteStorage {
+    fn sanctions(&self, kind: SanctionKind) -> Result<HashMap<Uuid, Sanction>> {
+        let mut statement = self.connection.prepare(
+            "SELECT user, issuer, reason, created, until FROM sanctions WHERE kind = ?1",
+        )?;
+        let rows = statement.query_map(params![kind_name(kind)], |row| {
+            Ok(Sanction {
+                user: parse_uuid(0, row.get(0)?)?,
+                issuer: row
+                    .get::<_, Option<String>>(1)?
+                    .map(|issuer| parse_uuid(1, issuer))
+                    .transpose()?,
+                reason: row.get(2)?,
+                created: row.get(3)?,
+                until: row.get(4)?,
+            })
+        })?;
+
+        let mut sanctions = HashMap::new();
+        for sanction in rows {
+            let sanction = sanction?;
+            sanctions.insert(sanction.user, sanction);
+        }
+        Ok(sanctions)
+    }
+
+    fn put_sanction(&mut self, kind: SanctionKind, sanction: &Sanction) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO sanctions (kind, user, issuer, reason, created, until)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                kind_name(kind),
+                sanction.user.to_string(),
+                sanction.issuer.map(|issuer| issuer.to_string()),
+                sanction.reason,
+                sanction.created,
+                sanction.until,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_sanctions(&mut self, kind: SanctionKind, users: &[Uuid]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for user in users {
+            transaction.execute(
+                "DELETE FROM sanctions WHERE kind = ?1 AND user = ?2",
+                params![kind_name(kind), user.to_string()],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn ip_bans(&self) -> Result<HashMap<IpRange, IpBan>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT cidr, issuer, reason, created, until FROM ip_bans")?;
+        let rows = statement.query_map([], |row| {
+            Ok(IpBan {
+                range: parse_text(0, row.get(0)?)?,
+                issuer: row
+                    .get::<_, Option<String>>(1)?
+                    .map(|issuer| parse_uuid(1, issuer))
+                    .transpose()?,
+                reason: row.get(2)?,
+                created: row.get(3)?,
+                until: row.get(4)?,
+            })
+        })?;
+
+        let mut bans = HashMap::new();
+        for ban in rows {
+            let ban = ban?;
+            bans.insert(ban.range, ban);
+        }
+        Ok(bans)
+    }
+
+    fn put_ip_ban(&mut self, ban: &IpBan) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO ip_bans (cidr, issuer, reason, created, until)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                ban.range.to_string(),
+                ban.issuer.map(|issuer| issuer.to_string()),
+                ban.reason,
+                ban.created,
+                ban.until,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_ip_bans(&mut self, ranges: &[IpRange]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        for range in ranges {
+            transaction.execute(
+                "DELETE FROM ip_bans WHERE cidr = ?1",
+                params![range.to_string()],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn moderators(&self) -> Result<HashSet<Uuid>> {
+        let mut statement = self.connection.prepare("SELECT user FROM moderators")?;
+        let rows = statement.query_map([], |row| parse_uuid(0, row.get(0)?))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn role_assignments(&self) -> Result<HashMap<Uuid, BTreeSet<String>>> {
+        let mut statement = self.connection.prepare("SELECT user, role FROM roles")?;
+        let rows = statement.query_map([], |row| {
+            Ok((parse_uuid(0, row.get(0)?)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut assignments: HashMap<Uuid, BTreeSet<String>> = HashMap::new();
+        for row in rows {
+            let (user, role) = row?;
+            assignments.entry(user).or_default().insert(role);
+        }
+        Ok(assignments)
+    }
+
+    fn set_roles(&mut self, user: &Uuid, roles: &BTreeSet<String>) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "DELETE FROM roles WHERE user = ?1",
+            params![user.to_string()],
+        )?;
+        for role in roles {
+            transaction.execute(
+                "INSERT INTO roles (user, role) VALUES (?1, ?2)",
+                params![user.to_string(), role],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn user(&self, uuid: &Uuid) -> Result<Option<UserRecord>> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT uuid, name, first_seen, last_seen FROM users WHERE uuid = ?1",
+                params![uuid.to_string()],
+                read_user,
+            )
+            .optional()?)
+    }
+
+    fn user_by_name(&self, name: &str) -> Result<Option<UserRecord>> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT uuid, name, first_seen, last_seen FROM users
+                 WHERE name = ?1 COLLATE NOCASE ORDER BY last_seen DESC LIMIT 1",
+                params![name],
+                read_user,
+            )
+            .optional()?)
+    }
+
+    fn put_user(&mut self, user: &UserRecord) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO users (uuid, name, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                user.uuid.to_string(),
+                user.name,
+                user.first_seen,
+                user.last_seen,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn put_address(&mut self, user: &Uuid, ip: IpAddr, time: u64) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO addresses (user, ip, first_seen, last_seen) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (user, ip) DO UPDATE SET last_seen = excluded.last_seen",
+            params![user.to_string(), ip.to_string(), time],
+        )?;
+        Ok(())
+    }
+
+    fn addresses_of(&self, user: &Uuid) -> Result<Vec<AddressRecord>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT user, ip, first_seen, last_seen FROM addresses WHERE user = ?1")?;
+        let rows = statement.query_map(params![user.to_string()], read_address)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn users_from(&self, ips: &[IpAddr]) -> Result<Vec<AddressRecord>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT user, ip, first_seen, last_seen FROM addresses WHERE ip = ?1")?;
+        let mut records = Vec::new();
+        for ip in ips {
+            for record in statement.query_map(params![ip.to_string()], read_address)? {
+                records.push(record?);
+            }
+        }
+        Ok(records)
+    }
+
+    fn prune_addresses(&mut self, before: u64) -> Result<usize> {
+        Ok(self.connection.execute(
+            "DELETE FROM addresses WHERE last_seen < ?1",
+            params![before],
+        )?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.connection.cache_flush()?;
+        Ok(())
+    }
+
+    fn watched_paths(&self) -> Vec<&Path> {
+        vec![&self.path]
+    }
+}
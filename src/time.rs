@@ -0,0 +1,9 @@
+use std::time::SystemTime;
+
+/// Returns the seconds since the unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("system time is somehow before the unix epoch")
+        .as_secs()
+}